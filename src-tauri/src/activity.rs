@@ -0,0 +1,339 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+use fit_file::fit_file;
+use ts_rs::TS;
+
+use crate::history::{History, Lap, Ride, Sample};
+
+// seconds between the unix epoch and the FIT epoch (1989-12-31T00:00:00Z)
+const FIT_EPOCH_OFFSET: u64 = 631065600;
+const FIT_SPORT_CYCLING: u8 = 2;
+
+// gaps between records longer than this are treated as the activity being
+// paused and aren't filled in
+const MAX_RECORD_GAP: u32 = 5;
+
+#[derive(Clone, Debug, serde::Serialize, TS)]
+#[ts(export, export_to = "../src/types/ImportFailure.ts")]
+pub struct ImportFailure {
+    pub path: String,
+    pub error: String,
+}
+
+#[derive(Clone, Debug, Default, serde::Serialize, TS)]
+#[ts(export, export_to = "../src/types/ImportReport.ts")]
+pub struct ImportReport {
+    pub imported: u32,
+    pub duplicates: u32,
+    pub failures: Vec<ImportFailure>,
+}
+
+struct Record {
+    timestamp: u32,
+    power: Option<u16>,
+    cadence: Option<u8>,
+    heart_rate: Option<u8>,
+    // metres per second
+    speed: Option<f64>,
+    // metres
+    distance: Option<f64>,
+}
+
+struct LapRecord {
+    start_time: u32,
+    // milliseconds
+    total_elapsed_time: u32,
+}
+
+#[derive(Default)]
+struct ActivityConstructor {
+    start_time: Option<u32>,
+    sport: Option<u8>,
+    records: Vec<Record>,
+    laps: Vec<LapRecord>,
+}
+
+fn fit_message_callback(
+    timestamp: u32,
+    global_msg_num: u16,
+    _local_msg_num: u8,
+    _message_index: u16,
+    fields: Vec<fit_file::FitFieldValue>,
+    data: &mut ActivityConstructor,
+) {
+    if global_msg_num == fit_file::GLOBAL_MSG_NUM_RECORD {
+        let record = fit_file::FitRecordMsg::new(fields);
+
+        data.records.push(Record {
+            timestamp,
+            power: record.power,
+            cadence: record.cadence,
+            heart_rate: record.heart_rate,
+            speed: record
+                .enhanced_speed
+                .or(record.speed.map(u32::from))
+                .map(|s| s as f64 / 1000.),
+            distance: record.distance.map(|d| d as f64 / 100.),
+        });
+    } else if global_msg_num == fit_file::GLOBAL_MSG_NUM_SESSION {
+        let session = fit_file::FitSessionMsg::new(fields);
+
+        if data.start_time.is_none() {
+            data.start_time = session.start_time;
+        }
+        if data.sport.is_none() {
+            data.sport = session.sport;
+        }
+    } else if global_msg_num == fit_file::GLOBAL_MSG_NUM_LAP {
+        let lap = fit_file::FitLapMsg::new(fields);
+
+        if let (Some(start_time), Some(total_elapsed_time)) =
+            (lap.start_time, lap.total_elapsed_time)
+        {
+            data.laps.push(LapRecord {
+                start_time,
+                total_elapsed_time,
+            });
+        }
+    }
+}
+
+fn samples_from_records(records: &[Record], start: u32) -> Vec<Sample> {
+    let mut samples: Vec<Sample> = vec![];
+
+    for (i, record) in records.iter().enumerate() {
+        if record.timestamp < start {
+            continue;
+        }
+
+        // fill short gaps by holding the previous record's values
+        if let Some(previous) = i.checked_sub(1).and_then(|p| records.get(p)) {
+            let gap = record.timestamp.saturating_sub(previous.timestamp);
+            if previous.timestamp >= start && gap > 1 && gap <= MAX_RECORD_GAP {
+                for t in previous.timestamp + 1..record.timestamp {
+                    samples.push(Sample {
+                        elapsed: t - start,
+                        power: previous.power,
+                        cadence: previous.cadence,
+                        heart_rate: previous.heart_rate,
+                        speed: previous.speed,
+                        distance: previous.distance,
+                    });
+                }
+            }
+        }
+
+        if samples
+            .last()
+            .is_some_and(|s| s.elapsed >= record.timestamp - start)
+        {
+            continue;
+        }
+
+        samples.push(Sample {
+            elapsed: record.timestamp - start,
+            power: record.power,
+            cadence: record.cadence,
+            heart_rate: record.heart_rate,
+            speed: record.speed,
+            distance: record.distance,
+        });
+    }
+
+    samples
+}
+
+fn laps_from_records(laps: &[LapRecord], start: u32, samples: &[Sample]) -> Vec<Lap> {
+    laps.iter()
+        .filter(|lap| lap.start_time >= start)
+        .map(|lap| {
+            let duration = (lap.total_elapsed_time as f64 / 1000.).round() as u32;
+            Lap::new(lap.start_time - start, duration, samples)
+        })
+        .collect()
+}
+
+pub fn load_activity(
+    data: impl std::io::Read,
+    title: String,
+    ftp: Option<u16>,
+) -> Result<Ride, String> {
+    let mut constructor = ActivityConstructor::default();
+
+    let mut reader = BufReader::new(data);
+
+    fit_file::read(&mut reader, fit_message_callback, &mut constructor)
+        .map_err(|e| format!("reading fit file: {}", e))?;
+
+    if constructor
+        .sport
+        .is_some_and(|sport| sport != FIT_SPORT_CYCLING)
+    {
+        return Err("not a cycling activity".into());
+    }
+
+    let start = constructor
+        .start_time
+        .or_else(|| constructor.records.first().map(|r| r.timestamp))
+        .ok_or("no records in activity")?;
+
+    let samples = samples_from_records(&constructor.records, start);
+    if samples.is_empty() {
+        return Err("no records in activity".into());
+    }
+
+    let laps = laps_from_records(&constructor.laps, start, &samples);
+
+    let mut ride = Ride::from_samples(start as u64 + FIT_EPOCH_OFFSET, title, samples, ftp);
+    ride.laps = laps;
+
+    Ok(ride)
+}
+
+fn activity_paths(paths: &[String]) -> Vec<PathBuf> {
+    let mut result = vec![];
+
+    for path in paths.iter().map(PathBuf::from) {
+        if path.is_dir() {
+            if let Ok(entries) = std::fs::read_dir(&path) {
+                let mut files = entries
+                    .filter_map(|entry| entry.ok().map(|e| e.path()))
+                    .filter(|path| {
+                        path.extension()
+                            .is_some_and(|ext| ext.eq_ignore_ascii_case("fit"))
+                    })
+                    .collect::<Vec<_>>();
+                files.sort();
+                result.extend(files);
+            } else {
+                result.push(path);
+            }
+        } else {
+            result.push(path);
+        }
+    }
+
+    result
+}
+
+fn import_activity(path: &Path, ftp: Option<u16>) -> Result<Ride, String> {
+    let file = File::open(path).map_err(|e| format!("open file: {}", e))?;
+
+    let title = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "Imported activity".into());
+
+    load_activity(file, title, ftp)
+}
+
+pub fn import_activities(
+    history: &mut History,
+    paths: &[String],
+    ftp: Option<u16>,
+) -> ImportReport {
+    let mut report = ImportReport::default();
+
+    for path in activity_paths(paths) {
+        let result = import_activity(&path, ftp).and_then(|ride| {
+            if history.has_ride_near(ride.summary.start_time) {
+                Ok(false)
+            } else {
+                history.save(&ride).map(|_| true)
+            }
+        });
+
+        match result {
+            Ok(true) => report.imported += 1,
+            Ok(false) => report.duplicates += 1,
+            Err(error) => report.failures.push(ImportFailure {
+                path: path.display().to_string(),
+                error,
+            }),
+        }
+    }
+
+    report
+}
+
+#[cfg(test)]
+mod test {
+    use crate::activity::{laps_from_records, samples_from_records, LapRecord, Record};
+    use crate::history::Lap;
+
+    fn record(timestamp: u32, power: u16) -> Record {
+        Record {
+            timestamp,
+            power: Some(power),
+            cadence: Some(90),
+            heart_rate: None,
+            speed: Some(power as f64 / 25.),
+            distance: Some((timestamp - 100) as f64 * 8.),
+        }
+    }
+
+    #[test]
+    fn it_resamples_records_to_one_second() {
+        let records = vec![
+            record(100, 200),
+            record(101, 210),
+            record(104, 250),
+            record(104, 255),
+            record(120, 300),
+        ];
+
+        let samples = samples_from_records(&records, 100);
+
+        let elapsed = samples.iter().map(|s| s.elapsed).collect::<Vec<_>>();
+        assert_eq!(elapsed, vec![0, 1, 2, 3, 4, 20]);
+
+        let power = samples.iter().map(|s| s.power.unwrap()).collect::<Vec<_>>();
+        assert_eq!(power, vec![200, 210, 210, 210, 250, 300]);
+
+        let speed = samples.iter().map(|s| s.speed.unwrap()).collect::<Vec<_>>();
+        assert_eq!(speed, vec![8., 8.4, 8.4, 8.4, 10., 12.]);
+
+        let distance = samples
+            .iter()
+            .map(|s| s.distance.unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(distance, vec![0., 8., 8., 8., 32., 160.]);
+    }
+
+    #[test]
+    fn it_converts_laps_to_ride_offsets() {
+        let records = (100..160)
+            .map(|t| record(t, if t < 130 { 200 } else { 300 }))
+            .collect::<Vec<_>>();
+        let samples = samples_from_records(&records, 100);
+
+        let laps = vec![
+            LapRecord {
+                start_time: 100,
+                total_elapsed_time: 30_000,
+            },
+            LapRecord {
+                start_time: 130,
+                total_elapsed_time: 29_600,
+            },
+        ];
+
+        assert_eq!(
+            laps_from_records(&laps, 100, &samples),
+            vec![
+                Lap {
+                    start: 0,
+                    duration: 30,
+                    average_power: 200,
+                },
+                Lap {
+                    start: 30,
+                    duration: 30,
+                    average_power: 300,
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,92 @@
+use std::time::{Duration, Instant};
+
+use crate::config;
+
+// Tracks time the rider has spent stopped so the executor can leave it out of
+// the session clock. Pauses once power and cadence have both been at or below
+// their thresholds for the configured delay, and resumes as soon as either
+// goes above.
+pub struct AutoPause {
+    config: config::AutoPause,
+    stopped_since: Option<Instant>,
+    paused_at: Option<Instant>,
+    paused_for: Duration,
+}
+
+impl AutoPause {
+    pub fn new(config: config::AutoPause) -> AutoPause {
+        AutoPause {
+            config,
+            stopped_since: None,
+            paused_at: None,
+            paused_for: Duration::ZERO,
+        }
+    }
+
+    pub fn update(&mut self, power: Option<u16>, cadence: Option<u8>, now: Instant) {
+        // devices stop broadcasting when the rider stops, so no data counts
+        let stopped = power.unwrap_or(0) <= self.config.power_threshold
+            && cadence.unwrap_or(0) <= self.config.cadence_threshold;
+
+        if !stopped {
+            self.stopped_since = None;
+            if let Some(paused_at) = self.paused_at.take() {
+                self.paused_for += now.saturating_duration_since(paused_at);
+            }
+            return;
+        }
+
+        let stopped_since = *self.stopped_since.get_or_insert(now);
+
+        if self.paused_at.is_none()
+            && now.saturating_duration_since(stopped_since)
+                >= Duration::from_secs(self.config.delay as u64)
+        {
+            self.paused_at = Some(now);
+        }
+    }
+
+    pub fn paused(&self) -> bool {
+        self.paused_at.is_some()
+    }
+
+    // total time paused, including a pause still in progress
+    pub fn paused_for(&self, now: Instant) -> Duration {
+        self.paused_for
+            + self.paused_at.map_or(Duration::ZERO, |paused_at| {
+                now.saturating_duration_since(paused_at)
+            })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use crate::auto_pause::AutoPause;
+    use crate::config;
+
+    #[test]
+    fn it_pauses_after_the_delay_and_resumes_on_pedalling() {
+        let mut auto_pause = AutoPause::new(config::AutoPause {
+            delay: 3,
+            power_threshold: 0,
+            cadence_threshold: 0,
+        });
+        let start = Instant::now();
+        let at = |s: u64| start + Duration::from_secs(s);
+
+        auto_pause.update(Some(200), Some(90), at(0));
+        auto_pause.update(Some(0), None, at(1));
+        auto_pause.update(None, None, at(3));
+        assert!(!auto_pause.paused());
+
+        auto_pause.update(None, None, at(4));
+        assert!(auto_pause.paused());
+        assert_eq!(auto_pause.paused_for(at(6)), Duration::from_secs(2));
+
+        auto_pause.update(Some(150), Some(0), at(10));
+        assert!(!auto_pause.paused());
+        assert_eq!(auto_pause.paused_for(at(20)), Duration::from_secs(6));
+    }
+}
@@ -0,0 +1,85 @@
+use crate::config::{self, CadenceGuardAction};
+
+// Watches cadence during ERG steps, updated once per second. Once it has been
+// below the minimum for long enough the configured action applies until
+// cadence is back up to the recovery cadence.
+pub struct CadenceGuard {
+    config: config::CadenceGuard,
+    low_for: u32,
+    engaged: bool,
+}
+
+impl CadenceGuard {
+    pub fn new(config: config::CadenceGuard) -> CadenceGuard {
+        CadenceGuard {
+            config,
+            low_for: 0,
+            engaged: false,
+        }
+    }
+
+    pub fn update(&mut self, cadence: Option<u8>) {
+        // hold the current state without cadence data
+        let cadence = match cadence {
+            Some(cadence) => cadence,
+            None => return,
+        };
+
+        if self.engaged {
+            if cadence >= self.config.recovery_cadence {
+                self.engaged = false;
+                self.low_for = 0;
+            }
+        } else if cadence < self.config.min_cadence {
+            self.low_for += 1;
+            self.engaged = self.low_for >= self.config.delay;
+        } else {
+            self.low_for = 0;
+        }
+    }
+
+    // resets when the rider leaves ERG mode, so it's only judged on ERG steps
+    pub fn reset(&mut self) {
+        self.low_for = 0;
+        self.engaged = false;
+    }
+
+    pub fn action(&self) -> Option<CadenceGuardAction> {
+        self.engaged.then_some(self.config.action)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cadence_guard::CadenceGuard;
+    use crate::config::{self, CadenceGuardAction};
+
+    #[test]
+    fn it_eases_off_until_cadence_recovers() {
+        let mut guard = CadenceGuard::new(config::CadenceGuard {
+            min_cadence: 50,
+            recovery_cadence: 70,
+            delay: 3,
+            action: CadenceGuardAction::ReducePower(0.5),
+        });
+
+        // a brief dip doesn't count
+        guard.update(Some(45));
+        guard.update(Some(45));
+        guard.update(Some(80));
+        guard.update(Some(45));
+        assert_eq!(guard.action(), None);
+
+        guard.update(Some(40));
+        guard.update(Some(35));
+        assert_eq!(guard.action(), Some(CadenceGuardAction::ReducePower(0.5)));
+
+        // back above the minimum isn't enough to restore the target
+        guard.update(Some(60));
+        guard.update(None);
+        assert!(guard.action().is_some());
+
+        guard.update(Some(72));
+        assert_eq!(guard.action(), None);
+    }
+}
@@ -0,0 +1,170 @@
+use ts_rs::TS;
+
+use crate::config;
+use crate::training_load::SECONDS_PER_DAY;
+
+const PAGE_CALIBRATION_REQUEST: u8 = 1;
+const PAGE_CALIBRATION_PROGRESS: u8 = 2;
+
+const SPIN_DOWN_BIT: u8 = 0x80;
+const ZERO_OFFSET_BIT: u8 = 0x40;
+
+const TEMPERATURE_INVALID: u8 = 0xff;
+const VALUE_INVALID: u16 = 0xffff;
+
+// calibrations older than this are reported as stale
+const STALE_AFTER_DAYS: u64 = 30;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize, TS)]
+#[serde(rename_all = "snake_case")]
+#[ts(export, export_to = "../src/types/CalibrationKind.ts")]
+pub enum CalibrationKind {
+    SpinDown,
+    ZeroOffset,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, TS)]
+#[serde(rename_all = "snake_case")]
+#[ts(export, export_to = "../src/types/CalibrationCondition.ts")]
+pub enum CalibrationCondition {
+    NotApplicable,
+    TooLow,
+    Ok,
+    TooHigh,
+}
+
+impl From<u8> for CalibrationCondition {
+    fn from(value: u8) -> Self {
+        match value {
+            1 => CalibrationCondition::TooLow,
+            2 => CalibrationCondition::Ok,
+            3 => CalibrationCondition::TooHigh,
+            _ => CalibrationCondition::NotApplicable,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, TS)]
+#[ts(export, export_to = "../src/types/CalibrationProgress.ts")]
+pub struct CalibrationProgress {
+    pub spin_down_pending: bool,
+    pub zero_offset_pending: bool,
+    pub temperature_condition: CalibrationCondition,
+    pub speed_condition: CalibrationCondition,
+    // degrees Celsius
+    pub temperature: Option<f64>,
+    // metres per second
+    pub target_speed: Option<f64>,
+    // milliseconds
+    pub target_spin_down_time: Option<u16>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, TS)]
+#[ts(export, export_to = "../src/types/CalibrationResult.ts")]
+pub struct CalibrationResult {
+    pub spin_down_success: bool,
+    pub zero_offset_success: bool,
+    pub temperature: Option<f64>,
+    pub zero_offset: Option<u16>,
+    // milliseconds
+    pub spin_down_time: Option<u16>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, TS)]
+#[ts(export, export_to = "../src/types/CalibrationStatus.ts")]
+pub struct CalibrationStatus {
+    pub last: Option<config::Calibration>,
+    pub stale: bool,
+}
+
+pub fn request_payload(kind: CalibrationKind) -> [u8; 8] {
+    let request = match kind {
+        CalibrationKind::SpinDown => SPIN_DOWN_BIT,
+        CalibrationKind::ZeroOffset => ZERO_OFFSET_BIT,
+    };
+
+    // temperature, zero offset and spin-down time are only set in responses
+    [
+        PAGE_CALIBRATION_REQUEST,
+        request,
+        0x00,
+        TEMPERATURE_INVALID,
+        0xff,
+        0xff,
+        0xff,
+        0xff,
+    ]
+}
+
+fn temperature(value: u8) -> Option<f64> {
+    (value != TEMPERATURE_INVALID).then(|| value as f64 / 2. - 25.)
+}
+
+fn optional_u16(low: u8, high: u8) -> Option<u16> {
+    Some(u16::from_le_bytes([low, high])).filter(|&v| v != VALUE_INVALID)
+}
+
+pub fn decode_progress(payload: &[u8; 8]) -> Option<CalibrationProgress> {
+    if payload[0] != PAGE_CALIBRATION_PROGRESS {
+        return None;
+    }
+
+    Some(CalibrationProgress {
+        spin_down_pending: payload[1] & SPIN_DOWN_BIT != 0,
+        zero_offset_pending: payload[1] & ZERO_OFFSET_BIT != 0,
+        temperature_condition: (payload[2] >> 4 & 0x03).into(),
+        speed_condition: (payload[2] >> 6).into(),
+        temperature: temperature(payload[3]),
+        target_speed: optional_u16(payload[4], payload[5]).map(|s| s as f64 / 1000.),
+        target_spin_down_time: optional_u16(payload[6], payload[7]),
+    })
+}
+
+pub fn decode_result(payload: &[u8; 8]) -> Option<CalibrationResult> {
+    if payload[0] != PAGE_CALIBRATION_REQUEST {
+        return None;
+    }
+
+    Some(CalibrationResult {
+        spin_down_success: payload[1] & SPIN_DOWN_BIT != 0,
+        zero_offset_success: payload[1] & ZERO_OFFSET_BIT != 0,
+        // byte 2 is reserved
+        temperature: temperature(payload[3]),
+        zero_offset: optional_u16(payload[4], payload[5]),
+        spin_down_time: optional_u16(payload[6], payload[7]),
+    })
+}
+
+pub fn status(last: Option<config::Calibration>, now: u64) -> CalibrationStatus {
+    CalibrationStatus {
+        last,
+        stale: last.is_none_or(|c| now.saturating_sub(c.date) > STALE_AFTER_DAYS * SECONDS_PER_DAY),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::calibration::{self, CalibrationCondition};
+
+    #[test]
+    fn it_decodes_progress_and_result_pages() {
+        let progress = calibration::decode_progress(&[2, 0x80, 0x90, 0x5a, 0x60, 0x22, 0xff, 0xff])
+            .expect("page decodes");
+
+        assert!(progress.spin_down_pending);
+        assert_eq!(progress.speed_condition, CalibrationCondition::Ok);
+        assert_eq!(progress.temperature_condition, CalibrationCondition::TooLow);
+        assert_eq!(progress.temperature, Some(20.));
+        assert_eq!(progress.target_speed, Some(8.8));
+        assert_eq!(progress.target_spin_down_time, None);
+
+        let result = calibration::decode_result(&[1, 0x80, 0xff, 0x5a, 0xff, 0xff, 0x2c, 0x01])
+            .expect("page decodes");
+
+        assert!(result.spin_down_success);
+        assert!(!result.zero_offset_success);
+        assert_eq!(result.temperature, Some(20.));
+        assert_eq!(result.spin_down_time, Some(300));
+        assert_eq!(result.zero_offset, None);
+    }
+}
@@ -1,4 +1,10 @@
-#[derive(Clone, Copy, Debug, serde::Deserialize, serde::Serialize)]
+use std::path::{Path, PathBuf};
+
+use ts_rs::TS;
+
+use crate::calibration::CalibrationKind;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
 pub struct DevicePairing {
     pub device_id: u16,
     pub transmission_type: u8,
@@ -13,13 +19,501 @@ impl From<DevicePairing> for antrs::device::DevicePairing {
     }
 }
 
-#[derive(Debug, serde::Deserialize, serde::Serialize)]
+// Each role accepts a single pairing or a list of candidates, so configs
+// written before multiple pairings were supported still load.
+#[derive(Clone, Debug, Default, serde::Deserialize, serde::Serialize)]
 pub struct Pairings {
-    pub heart_rate_monitor: DevicePairing,
-    pub fitness_equipment: DevicePairing,
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub heart_rate_monitor: Vec<DevicePairing>,
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub fitness_equipment: Vec<DevicePairing>,
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub power_meter: Vec<DevicePairing>,
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub speed_cadence_sensor: Vec<DevicePairing>,
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub speed_sensor: Vec<DevicePairing>,
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub cadence_sensor: Vec<DevicePairing>,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+enum OneOrMany {
+    One(DevicePairing),
+    Many(Vec<DevicePairing>),
+}
+
+fn one_or_many<'de, D>(deserializer: D) -> Result<Vec<DevicePairing>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    Ok(
+        match <OneOrMany as serde::Deserialize>::deserialize(deserializer)? {
+            OneOrMany::One(pairing) => vec![pairing],
+            OneOrMany::Many(pairings) => pairings,
+        },
+    )
+}
+
+// Adds a pairing as the preferred candidate for its role, replacing any
+// existing pairing for the same device.
+pub fn add_pairing(pairings: &mut Vec<DevicePairing>, pairing: DevicePairing) {
+    pairings.retain(|p| p.device_id != pairing.device_id);
+    pairings.insert(0, pairing);
+}
+
+#[derive(Clone, Copy, Debug, serde::Deserialize, serde::Serialize)]
+pub struct CriticalPower {
+    pub critical_power: u16,
+    pub w_prime: u32,
+    // when set, targets above CP are limited so that W' balance stays above this
+    pub w_prime_floor: Option<u32>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Source {
+    Trainer,
+    PowerMeter,
+    SpeedCadence,
+    // speed and distance modelled from power
+    Virtual,
+}
+
+// Order in which devices are used for values more than one of them reports.
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+pub struct SourcePriority {
+    pub power: Vec<Source>,
+    pub cadence: Vec<Source>,
+    pub speed: Vec<Source>,
+}
+
+impl Default for SourcePriority {
+    fn default() -> Self {
+        SourcePriority {
+            power: vec![Source::PowerMeter, Source::Trainer],
+            cadence: vec![Source::SpeedCadence, Source::PowerMeter, Source::Trainer],
+            speed: vec![Source::SpeedCadence, Source::Virtual, Source::Trainer],
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, serde::Deserialize, serde::Serialize, TS)]
+#[ts(export, export_to = "../src/types/Calibration.ts")]
+pub struct Calibration {
+    pub kind: CalibrationKind,
+    // unix seconds
+    #[ts(type = "number")]
+    pub date: u64,
+    pub zero_offset: Option<u16>,
+    pub spin_down_time: Option<u16>,
+    pub temperature: Option<f64>,
+}
+
+// Sent to the trainer for its speed and simulation calculations, and used for
+// virtual speed.
+#[derive(Clone, Copy, Debug, PartialEq, serde::Deserialize, serde::Serialize, TS)]
+#[ts(export, export_to = "../src/types/RiderProfile.ts")]
+pub struct RiderProfile {
+    // kilograms
+    pub rider_weight: f64,
+    pub bike_weight: f64,
+    pub gear_ratio: Option<f64>,
+    // override the simulation defaults when set
+    #[serde(default)]
+    pub drag_area: Option<f64>,
+    #[serde(default)]
+    pub rolling_resistance: Option<f64>,
+}
+
+// kilograms per cubic metre, dry air at 0 °C and 100 kPa, which gives the FE-C
+// default wind resistance coefficient of 0.51 kg/m for 0.4 m² of drag area
+const AIR_DENSITY: f64 = 1.275;
+
+// Resistance parameters sent to the trainer in simulation mode.
+#[derive(Clone, Copy, Debug, serde::Deserialize, serde::Serialize)]
+pub struct Simulation {
+    pub rolling_resistance: f64,
+    // drag coefficient times frontal area, in square metres
+    pub drag_area: f64,
+    pub drafting_factor: f64,
+}
+
+impl Default for Simulation {
+    fn default() -> Self {
+        Simulation {
+            rolling_resistance: 0.004,
+            drag_area: 0.4,
+            drafting_factor: 1.,
+        }
+    }
+}
+
+impl Simulation {
+    // kilograms per metre
+    pub fn wind_resistance_coefficient(&self) -> f64 {
+        self.drag_area * AIR_DENSITY
+    }
+}
+
+// Adjusts ERG targets so a separate power meter reads the set point.
+#[derive(Clone, Copy, Debug, serde::Deserialize, serde::Serialize)]
+pub struct PowerMatch {
+    // largest correction applied to the target, in watts
+    pub max_offset: u16,
+    // time constant of the power meter smoothing, in seconds
+    #[serde(default = "default_power_match_smoothing")]
+    pub smoothing: u32,
+}
+
+// What to do with an ERG target while cadence is too low to hold it.
+#[derive(Clone, Copy, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CadenceGuardAction {
+    // fraction of the target to ride at
+    ReducePower(f64),
+    // percent resistance to switch to
+    Resistance(f64),
+}
+
+// Eases off ERG targets when cadence collapses so the rider can spin back up.
+#[derive(Clone, Copy, Debug, serde::Deserialize, serde::Serialize)]
+pub struct CadenceGuard {
+    // rpm
+    pub min_cadence: u8,
+    pub recovery_cadence: u8,
+    // seconds below the minimum before easing off
+    #[serde(default = "default_cadence_guard_delay")]
+    pub delay: u32,
+    pub action: CadenceGuardAction,
+}
+
+// Pauses the session clock while the rider isn't pedalling.
+#[derive(Clone, Copy, Debug, serde::Deserialize, serde::Serialize)]
+pub struct AutoPause {
+    // seconds at or below both thresholds before pausing
+    #[serde(default = "default_auto_pause_delay")]
+    pub delay: u32,
+    // watts
+    #[serde(default)]
+    pub power_threshold: u16,
+    // rpm
+    #[serde(default)]
+    pub cadence_threshold: u8,
+}
+
+// Gains and limits for holding heart rate in ERG mode.
+#[derive(Clone, Copy, Debug, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct HeartRateControl {
+    // watts per beat per minute outside the target band
+    pub proportional_gain: f64,
+    // watts per beat per minute outside the band, per second
+    pub integral_gain: f64,
+    // watts per second
+    pub max_ramp: f64,
+    pub min_power: u16,
+    pub max_power: u16,
+}
+
+impl Default for HeartRateControl {
+    fn default() -> Self {
+        HeartRateControl {
+            proportional_gain: 2.,
+            integral_gain: 0.05,
+            max_ramp: 1.,
+            min_power: 50,
+            max_power: 400,
+        }
+    }
+}
+
+// Virtual gears for simulation mode, as ratios to the rider's real gear.
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+pub struct VirtualGearing {
+    // easiest first
+    pub ratios: Vec<f64>,
+    // index of the gear sessions start in
+    pub initial_gear: usize,
+}
+
+impl Default for VirtualGearing {
+    fn default() -> Self {
+        VirtualGearing {
+            ratios: vec![0.5, 0.6, 0.7, 0.8, 0.9, 1., 1.1, 1.2, 1.35, 1.5, 1.65, 1.8],
+            initial_gear: 5,
+        }
+    }
+}
+
+// grade added per unit of ratio above the real gear, so shifting is felt on
+// the flat as well as on climbs
+const GRADE_PER_RATIO: f64 = 4.;
+
+impl VirtualGearing {
+    // clamps a gear index to the table
+    pub fn gear(&self, gear: usize) -> usize {
+        gear.min(self.ratios.len().saturating_sub(1))
+    }
+
+    pub fn shift(&self, gear: usize, up: bool) -> usize {
+        if up {
+            self.gear(gear + 1)
+        } else {
+            self.gear(gear.saturating_sub(1))
+        }
+    }
+
+    // Grade sent to the trainer for a road grade in the given gear. Harder
+    // gears steepen climbs and flatten descents, easier gears the opposite.
+    pub fn effective_grade(&self, grade: f64, gear: usize) -> f64 {
+        let ratio = self.ratios.get(gear).copied().unwrap_or(1.);
+
+        let grade = if grade > 0. {
+            grade * ratio
+        } else {
+            grade / ratio
+        };
+
+        grade + (ratio - 1.) * GRADE_PER_RATIO
+    }
 }
 
-#[derive(Debug, serde::Deserialize, serde::Serialize)]
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
 pub struct AppConfig {
+    #[serde(default)]
     pub devices: Pairings,
+    pub ftp: Option<u16>,
+    pub critical_power: Option<CriticalPower>,
+    pub power_match: Option<PowerMatch>,
+    pub cadence_guard: Option<CadenceGuard>,
+    pub auto_pause: Option<AutoPause>,
+    #[serde(default)]
+    pub sources: SourcePriority,
+    // metres
+    #[serde(default = "default_wheel_circumference")]
+    pub wheel_circumference: f64,
+    pub last_calibration: Option<Calibration>,
+    pub rider: Option<RiderProfile>,
+    #[serde(default)]
+    pub simulation: Simulation,
+    #[serde(default)]
+    pub gearing: VirtualGearing,
+    #[serde(default)]
+    pub heart_rate_control: HeartRateControl,
+    #[serde(default = "default_history_dir")]
+    pub history_dir: PathBuf,
+}
+
+impl Default for AppConfig {
+    fn default() -> Self {
+        AppConfig {
+            devices: Pairings::default(),
+            ftp: None,
+            critical_power: None,
+            power_match: None,
+            cadence_guard: None,
+            auto_pause: None,
+            sources: SourcePriority::default(),
+            wheel_circumference: default_wheel_circumference(),
+            last_calibration: None,
+            rider: None,
+            simulation: Simulation::default(),
+            gearing: VirtualGearing::default(),
+            heart_rate_control: HeartRateControl::default(),
+            history_dir: default_history_dir(),
+        }
+    }
+}
+
+impl AppConfig {
+    pub fn load(path: impl AsRef<Path>) -> Result<AppConfig, String> {
+        let path = path.as_ref();
+
+        if !path.exists() {
+            return Ok(AppConfig::default());
+        }
+
+        let data = std::fs::read_to_string(path).map_err(|e| format!("read config file: {}", e))?;
+
+        let config: AppConfig =
+            toml::from_str(&data).map_err(|e| format!("parse config file: {}", e))?;
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    // rejects values that would divide by zero or stop the trainer responding
+    fn validate(&self) -> Result<(), String> {
+        if self.ftp == Some(0) {
+            return Err("ftp must be positive".into());
+        }
+
+        if let Some(cp) = self.critical_power {
+            if cp.critical_power == 0 {
+                return Err("critical_power must be positive".into());
+            }
+            if cp.w_prime == 0 {
+                return Err("w_prime must be positive".into());
+            }
+        }
+
+        if self.gearing.ratios.is_empty() {
+            return Err("gearing needs at least one ratio".into());
+        }
+        if self.gearing.ratios.iter().any(|&r| r.is_nan() || r <= 0.) {
+            return Err("gear ratios must be positive".into());
+        }
+
+        Ok(())
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), String> {
+        let data = toml::to_string(self).map_err(|e| format!("serialize config: {}", e))?;
+
+        std::fs::write(path, data).map_err(|e| format!("write config file: {}", e))
+    }
+
+    // simulation parameters with the rider's own drag area and rolling
+    // resistance, if they've set them
+    pub fn rider_simulation(&self) -> Simulation {
+        let rider = self.rider;
+
+        Simulation {
+            drag_area: rider
+                .and_then(|r| r.drag_area)
+                .unwrap_or(self.simulation.drag_area),
+            rolling_resistance: rider
+                .and_then(|r| r.rolling_resistance)
+                .unwrap_or(self.simulation.rolling_resistance),
+            ..self.simulation
+        }
+    }
+}
+
+fn default_history_dir() -> PathBuf {
+    "history".into()
+}
+
+fn default_wheel_circumference() -> f64 {
+    2.105
+}
+
+fn default_power_match_smoothing() -> u32 {
+    10
+}
+
+fn default_cadence_guard_delay() -> u32 {
+    3
+}
+
+fn default_auto_pause_delay() -> u32 {
+    3
+}
+
+#[cfg(test)]
+mod test {
+    use crate::config::{
+        AppConfig, CriticalPower, DevicePairing, HeartRateControl, VirtualGearing,
+    };
+
+    #[test]
+    fn it_loads_single_and_multiple_pairings() {
+        let config: AppConfig = toml::from_str(
+            r#"
+            ftp = 250
+
+            [devices.heart_rate_monitor]
+            device_id = 1234
+            transmission_type = 1
+
+            [[devices.fitness_equipment]]
+            device_id = 10
+            transmission_type = 5
+
+            [[devices.fitness_equipment]]
+            device_id = 20
+            transmission_type = 5
+            "#,
+        )
+        .expect("config parses");
+
+        assert_eq!(
+            config.devices.heart_rate_monitor,
+            vec![DevicePairing {
+                device_id: 1234,
+                transmission_type: 1
+            }]
+        );
+        assert_eq!(config.devices.fitness_equipment.len(), 2);
+
+        let config: AppConfig = toml::from_str("ftp = 250").expect("config parses");
+        assert!(config.devices.heart_rate_monitor.is_empty());
+    }
+
+    #[test]
+    fn it_loads_partial_heart_rate_control() {
+        let config: AppConfig = toml::from_str(
+            r#"
+            [heart_rate_control]
+            max_power = 300
+            "#,
+        )
+        .expect("config parses");
+
+        assert_eq!(config.heart_rate_control.max_power, 300);
+        assert_eq!(
+            config.heart_rate_control.min_power,
+            HeartRateControl::default().min_power
+        );
+    }
+
+    #[test]
+    fn it_scales_grade_with_the_gear() {
+        let gearing = VirtualGearing::default();
+        let neutral = gearing.initial_gear;
+
+        assert_eq!(gearing.effective_grade(5., neutral), 5.);
+
+        let hard = gearing.shift(neutral, true);
+        let easy = gearing.shift(neutral, false);
+        assert!(gearing.effective_grade(5., hard) > 5.);
+        assert!(gearing.effective_grade(0., hard) > 0.);
+        assert!(gearing.effective_grade(-5., hard) > -5.);
+        assert!(gearing.effective_grade(5., easy) < 5.);
+
+        // shifting stops at either end of the table
+        assert_eq!(
+            gearing.shift(gearing.ratios.len() - 1, true),
+            gearing.ratios.len() - 1
+        );
+        assert_eq!(gearing.shift(0, false), 0);
+    }
+
+    #[test]
+    fn it_rejects_non_positive_values() {
+        let valid = AppConfig::default();
+        assert!(valid.validate().is_ok());
+
+        let mut config = AppConfig::default();
+        config.ftp = Some(0);
+        assert!(config.validate().is_err());
+
+        let mut config = AppConfig::default();
+        config.critical_power = Some(CriticalPower {
+            critical_power: 250,
+            w_prime: 0,
+            w_prime_floor: None,
+        });
+        assert!(config.validate().is_err());
+
+        let mut config = AppConfig::default();
+        config.gearing.ratios = vec![];
+        assert!(config.validate().is_err());
+
+        let mut config = AppConfig::default();
+        config.gearing.ratios = vec![0.5, 0., 1.];
+        assert!(config.validate().is_err());
+    }
 }
@@ -0,0 +1,226 @@
+use ts_rs::TS;
+
+use crate::power_curve::PowerCurve;
+
+// mean-max durations used for fitting each model, in seconds
+const TWO_PARAMETER_RANGE: (u32, u32) = (180, 1200);
+const THREE_PARAMETER_RANGE: (u32, u32) = (10, 1800);
+
+// search range for the 3-parameter model's time offset, in seconds
+const TIME_OFFSET_RANGE: (i32, i32) = (-120, 0);
+
+const FTP_DURATION: f64 = 3600.;
+const FTP_CHANGE_THRESHOLD: f64 = 0.03;
+
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, TS)]
+#[ts(export, export_to = "../src/types/CriticalPowerModel.ts")]
+pub struct CriticalPowerModel {
+    pub critical_power: f64,
+    pub w_prime: f64,
+    pub time_offset: f64,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, TS)]
+#[ts(export, export_to = "../src/types/CriticalPowerEstimate.ts")]
+pub struct CriticalPowerEstimate {
+    pub two_parameter: Option<CriticalPowerModel>,
+    pub three_parameter: Option<CriticalPowerModel>,
+    pub ftp_estimate: Option<u16>,
+    pub configured_ftp: Option<u16>,
+    pub ftp_update_suggested: bool,
+}
+
+impl CriticalPowerModel {
+    pub fn power_at(&self, duration: f64) -> f64 {
+        self.critical_power + self.w_prime / (duration - self.time_offset)
+    }
+}
+
+fn points_in_range(curve: &PowerCurve, range: (u32, u32)) -> Vec<(f64, f64)> {
+    curve
+        .points
+        .iter()
+        .filter(|p| p.duration >= range.0 && p.duration <= range.1)
+        .map(|p| (p.duration as f64, p.power as f64))
+        .collect()
+}
+
+// least squares fit of y = slope * x + intercept, returning (slope, intercept, sse)
+fn linear_regression(points: &[(f64, f64)]) -> Option<(f64, f64, f64)> {
+    let n = points.len() as f64;
+    let mean_x = points.iter().map(|p| p.0).sum::<f64>() / n;
+    let mean_y = points.iter().map(|p| p.1).sum::<f64>() / n;
+
+    let sxx = points.iter().map(|p| (p.0 - mean_x).powi(2)).sum::<f64>();
+    if points.len() < 2 || sxx == 0. {
+        return None;
+    }
+
+    let sxy = points
+        .iter()
+        .map(|p| (p.0 - mean_x) * (p.1 - mean_y))
+        .sum::<f64>();
+
+    let slope = sxy / sxx;
+    let intercept = mean_y - slope * mean_x;
+    let sse = points
+        .iter()
+        .map(|p| (p.1 - (slope * p.0 + intercept)).powi(2))
+        .sum::<f64>();
+
+    Some((slope, intercept, sse))
+}
+
+// Fits work = CP * t + W' over efforts between 3 and 20 minutes.
+pub fn fit_two_parameter(curve: &PowerCurve) -> Option<CriticalPowerModel> {
+    let points = points_in_range(curve, TWO_PARAMETER_RANGE)
+        .into_iter()
+        .map(|(t, p)| (t, p * t))
+        .collect::<Vec<_>>();
+
+    if points.len() < 3 {
+        return None;
+    }
+
+    let (critical_power, w_prime, _) = linear_regression(&points)?;
+
+    if critical_power <= 0. || w_prime <= 0. {
+        return None;
+    }
+
+    Some(CriticalPowerModel {
+        critical_power,
+        w_prime,
+        time_offset: 0.,
+    })
+}
+
+// Fits P = W' / (t - k) + CP (Morton's 3-parameter model) by searching over
+// the time offset k and solving the remaining linear problem for each value.
+pub fn fit_three_parameter(curve: &PowerCurve) -> Option<CriticalPowerModel> {
+    let points = points_in_range(curve, THREE_PARAMETER_RANGE);
+
+    if points.len() < 4 {
+        return None;
+    }
+
+    let mut best: Option<(CriticalPowerModel, f64)> = None;
+
+    for offset in TIME_OFFSET_RANGE.0..=TIME_OFFSET_RANGE.1 {
+        let time_offset = offset as f64;
+
+        let transformed = points
+            .iter()
+            .map(|&(t, p)| (1. / (t - time_offset), p))
+            .collect::<Vec<_>>();
+
+        if let Some((w_prime, critical_power, sse)) = linear_regression(&transformed) {
+            if critical_power <= 0. || w_prime <= 0. {
+                continue;
+            }
+
+            if best.is_none_or(|(_, best_sse)| sse < best_sse) {
+                best = Some((
+                    CriticalPowerModel {
+                        critical_power,
+                        w_prime,
+                        time_offset,
+                    },
+                    sse,
+                ));
+            }
+        }
+    }
+
+    best.map(|(model, _)| model)
+}
+
+pub fn estimate(curve: &PowerCurve, configured_ftp: Option<u16>) -> CriticalPowerEstimate {
+    let two_parameter = fit_two_parameter(curve);
+    let three_parameter = fit_three_parameter(curve);
+
+    // the 3-parameter model behaves better at long durations, so prefer it
+    // for predicting the hour power
+    let ftp_estimate = three_parameter
+        .or(two_parameter)
+        .map(|model| model.power_at(FTP_DURATION).round() as u16);
+
+    let ftp_update_suggested = match (ftp_estimate, configured_ftp) {
+        (Some(estimate), Some(configured)) => {
+            (estimate as f64 - configured as f64).abs() / configured as f64 >= FTP_CHANGE_THRESHOLD
+        }
+        (Some(_), None) => true,
+        _ => false,
+    };
+
+    CriticalPowerEstimate {
+        two_parameter,
+        three_parameter,
+        ftp_estimate,
+        configured_ftp,
+        ftp_update_suggested,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::critical_power::{self, CriticalPowerModel};
+    use crate::power_curve::{PowerCurve, PowerCurvePoint, DURATIONS};
+
+    fn curve_from_model(model: CriticalPowerModel) -> PowerCurve {
+        PowerCurve {
+            points: DURATIONS
+                .iter()
+                .map(|&duration| PowerCurvePoint {
+                    duration,
+                    power: model.power_at(duration as f64).round() as u16,
+                    ride_id: 1,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn it_recovers_two_parameter_model() {
+        let curve = curve_from_model(CriticalPowerModel {
+            critical_power: 250.,
+            w_prime: 20000.,
+            time_offset: 0.,
+        });
+
+        let model = critical_power::fit_two_parameter(&curve).expect("model fits");
+
+        assert!((model.critical_power - 250.).abs() < 2.);
+        assert!((model.w_prime - 20000.).abs() < 500.);
+    }
+
+    #[test]
+    fn it_recovers_three_parameter_model() {
+        let curve = curve_from_model(CriticalPowerModel {
+            critical_power: 280.,
+            w_prime: 18000.,
+            time_offset: -30.,
+        });
+
+        let model = critical_power::fit_three_parameter(&curve).expect("model fits");
+
+        assert!((model.critical_power - 280.).abs() < 3.);
+        assert!((model.time_offset + 30.).abs() <= 5.);
+    }
+
+    #[test]
+    fn it_suggests_ftp_update_when_estimate_moves() {
+        let curve = curve_from_model(CriticalPowerModel {
+            critical_power: 250.,
+            w_prime: 20000.,
+            time_offset: 0.,
+        });
+
+        let estimate = critical_power::estimate(&curve, Some(220));
+        assert!(estimate.ftp_update_suggested);
+
+        let ftp = estimate.ftp_estimate.expect("ftp estimated");
+        let estimate = critical_power::estimate(&curve, Some(ftp));
+        assert!(!estimate.ftp_update_suggested);
+    }
+}
@@ -10,47 +10,251 @@ use antrs::{
     profile::fitness_equipment,
 };
 
-use crate::workout;
+use crate::auto_pause::AutoPause;
+use crate::cadence_guard::CadenceGuard;
+use crate::config::{AppConfig, CadenceGuardAction, Simulation, Source};
+use crate::heart_rate_control::{HeartRateController, HeartRateTarget};
+use crate::metrics::LiveMetrics;
+use crate::physics::{self, VirtualBike};
+use crate::power_match::PowerMatch;
+use crate::recorder::{Readings, Recording};
+use crate::route::{Route, RoutePosition};
+use crate::trainer_data::{self, MAX_TARGET_POWER};
+use crate::w_prime::WPrimeBalance;
+use crate::workout::{self, StepMode};
 
 #[derive(Clone, Copy, serde::Serialize, TS)]
 #[ts(export, export_to = "../src/types/WorkoutStatus.ts")]
 pub struct WorkoutStatus {
     pub step_index: usize,
     pub step_elapsed: u32,
+    pub mode: StepMode,
+    pub target_power: u16,
+    pub target_grade: Option<f64>,
+    pub target_limited: bool,
+    pub power_match_offset: Option<i16>,
+    // set while the target is following heart rate
+    pub heart_rate_target: Option<HeartRateTarget>,
+    // the target is eased off until cadence recovers
+    pub cadence_limited: bool,
+    // the clock is stopped because the rider isn't pedalling
+    pub paused: bool,
+}
+
+#[derive(Clone, Copy, serde::Serialize, TS)]
+#[ts(export, export_to = "../src/types/Telemetry.ts")]
+pub struct Telemetry {
+    pub elapsed: u32,
+    pub power_3s: f64,
+    pub power_10s: f64,
+    pub power_30s: f64,
+    pub average_power: f64,
+    pub normalized_power: f64,
+    pub intensity_factor: Option<f64>,
+    pub training_stress: Option<f64>,
+    pub work: f64,
+    pub average_cadence: Option<f64>,
+    pub average_heart_rate: Option<f64>,
+    pub w_prime_balance: Option<f64>,
+    pub cadence: Option<u8>,
+    pub speed: Option<f64>,
+    pub distance: Option<f64>,
+    // virtual gear, 1 being the easiest
+    pub gear: usize,
+}
+
+// State the executor shares with the UI and the device threads.
+#[derive(Clone)]
+pub struct Shared {
+    pub status: Arc<Mutex<Option<WorkoutStatus>>>,
+    pub telemetry: Arc<Mutex<Option<Telemetry>>>,
+    pub readings: Arc<Mutex<Readings>>,
+    pub control: Arc<Mutex<Control>>,
+    pub route_position: Arc<Mutex<Option<RoutePosition>>>,
+}
+
+// Target for free rides: watts in ERG mode, percent grade in simulation mode
+// or percent resistance in resistance mode.
+#[derive(Clone, Copy, Debug, PartialEq, serde::Deserialize, TS)]
+#[ts(export, export_to = "../src/types/ManualTarget.ts")]
+pub struct ManualTarget {
+    pub mode: StepMode,
+    pub value: f64,
+}
+
+// What the executor rides: a structured workout, a route whose grade follows
+// the rider's virtual distance along it, or a free ride at a target set from
+// the UI until it's stopped.
+pub enum Plan {
+    Workout(workout::Workout),
+    Route(Route),
+    FreeRide(ManualTarget),
 }
 
 pub struct Executor {
+    config: AppConfig,
     fe_state: super::FitnessEquipmentState,
     node: Arc<Mutex<Option<node::Node>>>,
-    status: Arc<Mutex<Option<WorkoutStatus>>>,
-    workout: workout::Workout,
+    shared: Shared,
+    plan: Plan,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Target {
+    Power(u16),
+    // grade in percent
+    Simulation(f64),
+    // percent of the trainer's maximum resistance
+    Resistance(f64),
 }
 
+impl Target {
+    // data page that sets the target, which command status pages refer to
+    fn page(&self) -> u8 {
+        match self {
+            Target::Power(_) => fitness_equipment::Command::TargetPower.into(),
+            Target::Simulation(_) => trainer_data::PAGE_TRACK_RESISTANCE,
+            Target::Resistance(_) => trainer_data::PAGE_BASIC_RESISTANCE,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
 enum CommandState {
     None,
-    Pending(u16, Instant),
-    Acked(u16),
+    Pending(Target, Instant),
+    Acked(Target),
+}
+
+impl CommandState {
+    // Applies a passing command status for the given data page.
+    fn acknowledge(self, command_id: u8, acked_power: Option<u16>) -> CommandState {
+        if command_id == Into::<u8>::into(fitness_equipment::Command::TargetPower) {
+            match acked_power {
+                Some(acked_power) => {
+                    let power = acked_power / 4;
+
+                    debug!("updating acked power: {}", power);
+                    CommandState::Acked(Target::Power(power))
+                }
+                None => self,
+            }
+        } else {
+            match self {
+                // other statuses don't echo the value, so assume it's the one
+                // pending for the same page
+                CommandState::Pending(target, _) if command_id == target.page() => {
+                    debug!("updating acked target: {:?}", target);
+                    CommandState::Acked(target)
+                }
+                _ => self,
+            }
+        }
+    }
+
+    fn command_required(&self, desired_target: Target, timeout: Duration) -> bool {
+        match *self {
+            CommandState::None => {
+                trace!("no command status, sending command");
+                true
+            }
+            CommandState::Pending(target, sent_at) => {
+                if target != desired_target || sent_at.elapsed() > timeout {
+                    warn!("pending command status, sending command; target: {:?}, desired_target: {:?}, sent_at.elapsed(): {}", target, desired_target, sent_at.elapsed().as_millis());
+                    true
+                } else {
+                    false
+                }
+            }
+            CommandState::Acked(target) => {
+                if target != desired_target {
+                    trace!(
+                        "acked command status, sending command; target: {:?}, desired_target: {:?}",
+                        target,
+                        desired_target
+                    );
+                    true
+                } else {
+                    false
+                }
+            }
+        }
+    }
+}
+
+// Shared with the UI so it can steer a running workout.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Control {
+    // rides the rest of the workout in simulation mode at this grade
+    pub grade_override: Option<f64>,
+    // virtual gear index, the configured initial gear until shifted
+    pub gear: Option<usize>,
+    // replaces the target a free ride started with
+    pub manual_target: Option<ManualTarget>,
+    // ERG targets follow heart rate while set
+    pub heart_rate_target: Option<HeartRateTarget>,
+    pub stop: bool,
 }
 
 impl Executor {
     pub fn new(
         node: Arc<Mutex<Option<node::Node>>>,
-        status: Arc<Mutex<Option<WorkoutStatus>>>,
-        workout: workout::Workout,
+        shared: Shared,
+        plan: Plan,
         fe_state: super::FitnessEquipmentState,
+        config: AppConfig,
     ) -> Executor {
         Executor {
+            config,
             fe_state,
             node,
-            status,
-            workout,
+            shared,
+            plan,
+        }
+    }
+
+    fn target_message(channel: u8, target: Target, simulation: &Simulation) -> Message {
+        match target {
+            Target::Power(power) => fitness_equipment::target_power_message(channel, power * 4),
+            Target::Simulation(grade) => antrs::message::acknowledged_data(
+                channel,
+                trainer_data::track_resistance_payload(grade, simulation),
+            ),
+            Target::Resistance(resistance) => antrs::message::acknowledged_data(
+                channel,
+                trainer_data::basic_resistance_payload(resistance),
+            ),
+        }
+    }
+
+    fn release_trainer(
+        node: &Mutex<Option<node::Node>>,
+        channel: u8,
+        matcher: impl Fn(Message) -> bool + Send + 'static,
+    ) {
+        let node = node.lock().unwrap();
+        if let Some(ref node) = *node {
+            if let Err(e) =
+                node.wait_for_message_after(Box::new(matcher), Duration::from_millis(400), || {
+                    node.write_message(
+                        antrs::message::acknowledged_data(
+                            channel,
+                            trainer_data::basic_resistance_payload(0.),
+                        ),
+                        Duration::from_millis(100),
+                    )
+                })
+            {
+                error!("releasing trainer: {}", e);
+            }
         }
     }
 
     fn power_control_loop(
         node: Arc<Mutex<Option<node::Node>>>,
-        control: crossbeam_channel::Receiver<u16>,
+        control: crossbeam_channel::Receiver<Target>,
         fe_state: super::FitnessEquipmentState,
+        simulation: Simulation,
     ) {
         let channel = fe_state.channel;
 
@@ -70,71 +274,78 @@ impl Executor {
         let command_status_timeout = Duration::from_millis(500);
 
         let mut command_state = CommandState::None;
-        let mut desired_power = None;
+        let mut desired_target = None;
+        let mut wind_resistance_sent = false;
 
         loop {
-            if let Some(request) = control.try_iter().last() {
-                debug!("updating desired power: {}", request);
-                desired_power = Some(request);
+            loop {
+                match control.try_recv() {
+                    Ok(request) => {
+                        debug!("updating desired target: {:?}", request);
+                        desired_target = Some(request);
+                    }
+                    Err(crossbeam_channel::TryRecvError::Empty) => break,
+                    Err(crossbeam_channel::TryRecvError::Disconnected) => {
+                        // the session is over, so leave the trainer free to
+                        // pedal rather than holding its last target
+                        Self::release_trainer(&node, channel, matcher);
+                        debug!("session ended, exiting control loop");
+                        return;
+                    }
+                }
             }
 
             for command_status in fe_state.receiver.try_iter() {
                 debug!("received command status: {:?}", command_status);
-                if command_status.command_id
-                    == Into::<u8>::into(fitness_equipment::Command::TargetPower)
-                {
-                    if command_status.command_status == antrs::message::CommandStatus::Pass {
-                        if let Some(acked_power) = command_status.target_power {
-                            let power = acked_power / 4;
-
-                            debug!("updating acked power: {}", power);
-                            command_state = CommandState::Acked(power);
-                        }
-                    } else {
-                        warn!(
-                            "command status is not a pass: {:?}",
-                            command_status.command_status
-                        );
-                    }
+
+                if command_status.command_status != antrs::message::CommandStatus::Pass {
+                    warn!(
+                        "command status is not a pass: {:?}",
+                        command_status.command_status
+                    );
+                    continue;
                 }
+
+                command_state = command_state
+                    .acknowledge(command_status.command_id, command_status.target_power);
             }
 
-            if let Some(desired_power) = desired_power {
-                let command_required = match command_state {
-                    CommandState::None => {
-                        trace!("no command status, sending command");
-                        true
-                    }
-                    CommandState::Pending(power, sent_at) => {
-                        if power != desired_power || sent_at.elapsed() > command_status_timeout {
-                            warn!("pending command status, sending command; power: {}, desired_power: {}, sent_at.elapsed(): {}", power, desired_power, sent_at.elapsed().as_millis());
-                            true
-                        } else {
-                            false
-                        }
-                    }
-                    CommandState::Acked(power) => {
-                        if power != desired_power {
-                            trace!("acked command status, sending command; power: {}, desired_power: {}", power, desired_power);
-                            true
-                        } else {
-                            false
-                        }
-                    }
-                };
+            if let Some(desired_target) = desired_target {
+                let command_required =
+                    command_state.command_required(desired_target, command_status_timeout);
 
                 if command_required {
-                    let erg = fitness_equipment::target_power_message(
-                        fe_state.channel,
-                        desired_power * 4,
-                    );
-
-                    let mut send_data_page_request = false;
-
                     let node = node.lock().unwrap();
                     if let Some(ref node) = *node {
+                        // wind resistance only needs sending once before the
+                        // first grade
+                        if matches!(desired_target, Target::Simulation(_)) && !wind_resistance_sent
+                        {
+                            match node.wait_for_message_after(Box::new(matcher), tx_timeout, || {
+                                node.write_message(
+                                    antrs::message::acknowledged_data(
+                                        fe_state.channel,
+                                        trainer_data::wind_resistance_payload(&simulation),
+                                    ),
+                                    send_timeout,
+                                )
+                            }) {
+                                Ok(_) => {
+                                    wind_resistance_sent = true;
+                                }
+                                Err(e) => {
+                                    error!("writing wind resistance message: {}", e);
+                                }
+                            }
+                        }
+
+                        let message =
+                            Self::target_message(fe_state.channel, desired_target, &simulation);
+
+                        let mut send_data_page_request = false;
+
                         match node.wait_for_message_after(Box::new(matcher), tx_timeout, || {
-                            node.write_message(erg, send_timeout)
+                            node.write_message(message, send_timeout)
                         }) {
                             Ok(_) => {
                                 send_data_page_request = true;
@@ -143,20 +354,23 @@ impl Executor {
                                 warn!("timeout waiting for command channel event");
                             }
                             Err(e) => {
-                                error!("writing power command message: {}", e);
+                                error!("writing target command message: {}", e);
                             }
                         }
 
                         if send_data_page_request {
                             match node.wait_for_message_after(Box::new(matcher), tx_timeout, || {
                                 node.write_message(
-                                    antrs::message::request_data_page(fe_state.channel, 71),
+                                    antrs::message::request_data_page(
+                                        fe_state.channel,
+                                        trainer_data::PAGE_COMMAND_STATUS,
+                                    ),
                                     send_timeout,
                                 )
                             }) {
                                 Ok(_) => {
                                     command_state =
-                                        CommandState::Pending(desired_power, Instant::now());
+                                        CommandState::Pending(desired_target, Instant::now());
                                 }
                                 Err(e) => {
                                     error!("writing data page request message: {}", e);
@@ -171,77 +385,404 @@ impl Executor {
         }
     }
 
-    pub fn execute(self) {
+    pub fn execute(self) -> Recording {
         let node = Arc::clone(&self.node);
         let (sender, receiver) = crossbeam_channel::unbounded();
         let fe_state = self.fe_state;
+        let capabilities = Arc::clone(&fe_state.capabilities);
+        let simulation = self.config.rider_simulation();
 
-        std::thread::spawn(move || Self::power_control_loop(node, receiver, fe_state));
+        let control_loop = std::thread::spawn(move || {
+            Self::power_control_loop(node, receiver, fe_state, simulation)
+        });
 
         let workout_start = Instant::now();
         let mut step_index = 0;
 
-        let power: u16 = self.workout.steps[step_index].set_point.try_into().unwrap();
-        sender.send(power).unwrap();
+        let mut recording = Recording::start();
+        let mut metrics = LiveMetrics::new(self.config.ftp);
+        let mut next_sample = 0;
 
-        debug!(
-            "starting workout at step: {}, need to set power to {}",
-            step_index, power,
-        );
+        let mut w_prime_balance = self
+            .config
+            .critical_power
+            .map(|cp| WPrimeBalance::new(cp.critical_power, cp.w_prime));
+        let w_prime_floor = self.config.critical_power.and_then(|cp| cp.w_prime_floor);
+        // whole seconds of pause already credited to the W' balance
+        let mut w_prime_paused = 0;
+
+        let mut power_match = self.config.power_match.map(PowerMatch::new);
+        let mut heart_rate_controller: Option<HeartRateController> = None;
+        let mut cadence_guard = self.config.cadence_guard.map(CadenceGuard::new);
+        let mut auto_pause = self.config.auto_pause.map(AutoPause::new);
+
+        let mut bike = VirtualBike::new(physics::mass(self.config.rider), simulation);
+        // grade the virtual bike rides at, flat unless simulating
+        let mut bike_grade = 0.;
+
+        let gearing = &self.config.gearing;
+        let mut gear = gearing.gear(gearing.initial_gear);
+
+        let mut target = None;
+
+        debug!("starting workout at step: {}", step_index);
 
         loop {
-            let (step_elapsed, step_changed) = {
-                let elapsed = workout_start.elapsed().as_millis();
+            let control = *self.shared.control.lock().unwrap();
 
-                let step_start = self.workout.steps[0..step_index]
-                    .iter()
-                    .fold(0, |a, e| a + e.duration as u128 * 1000);
+            if control.stop {
+                trace!("session stopped, exiting executor");
+                break;
+            }
 
-                let threshold = step_start + self.workout.steps[step_index].duration as u128 * 1000;
+            let now = Instant::now();
+
+            // time spent paused doesn't count towards steps or samples
+            let paused = match auto_pause {
+                Some(ref mut auto_pause) => {
+                    let (power, cadence) = {
+                        let readings = self.shared.readings.lock().unwrap();
+                        (readings.power(), readings.cadence())
+                    };
+                    auto_pause.update(power, cadence, now);
+                    auto_pause.paused()
+                }
+                None => false,
+            };
+            let active = now.duration_since(workout_start)
+                - auto_pause
+                    .as_ref()
+                    .map_or(Duration::ZERO, |a| a.paused_for(now));
 
-                if elapsed > threshold {
-                    step_index += 1;
+            let (step_elapsed, step_changed) = match self.plan {
+                Plan::Workout(ref wko) => {
+                    let elapsed = active.as_millis();
 
-                    if step_index >= self.workout.steps.len() {
-                        trace!("workout complete, exiting executor");
+                    let step_start = wko.steps[0..step_index]
+                        .iter()
+                        .fold(0, |a, e| a + e.duration as u128 * 1000);
+
+                    let threshold = step_start + wko.steps[step_index].duration as u128 * 1000;
+
+                    if elapsed > threshold {
+                        step_index += 1;
+
+                        if step_index >= wko.steps.len() {
+                            trace!("workout complete, exiting executor");
+                            break;
+                        }
+
+                        trace!("new step: {}, elapsed: {}", step_index, elapsed);
+
+                        (elapsed - threshold, true)
+                    } else {
+                        (elapsed - step_start, false)
+                    }
+                }
+                Plan::Route(ref route) => {
+                    if bike.distance() >= route.distance {
+                        trace!("route complete, exiting executor");
                         break;
                     }
 
-                    trace!("new step: {}, elapsed: {}", step_index, elapsed);
+                    (active.as_millis(), false)
+                }
+                Plan::FreeRide(_) => (active.as_millis(), false),
+            };
+
+            // W' keeps recovering at 0 W while paused, as no samples are taken
+            if let (Some(w_prime_balance), Some(auto_pause)) =
+                (w_prime_balance.as_mut(), auto_pause.as_ref())
+            {
+                let paused_for = auto_pause.paused_for(now).as_secs();
+                if paused_for > w_prime_paused {
+                    for _ in w_prime_paused..paused_for {
+                        w_prime_balance.update(0, 1.);
+                    }
+                    w_prime_paused = paused_for;
 
-                    (elapsed - threshold, true)
-                } else {
-                    (elapsed - step_start, false)
+                    if let Some(ref mut telemetry) = *self.shared.telemetry.lock().unwrap() {
+                        telemetry.w_prime_balance = Some(w_prime_balance.balance());
+                    }
+                }
+            }
+
+            let elapsed = active.as_secs() as u32;
+            let sampled = elapsed >= next_sample;
+            if sampled {
+                let readings = {
+                    let mut readings = self.shared.readings.lock().unwrap();
+
+                    bike.update(readings.power().unwrap_or(0), bike_grade, 1.);
+                    readings.set_speed(Source::Virtual, Some(bike.speed()));
+                    readings.set_distance(Source::Virtual, Some(bike.distance()));
+
+                    readings.clone()
+                };
+                recording.record(next_sample, &readings);
+
+                if let Plan::Route(ref route) = self.plan {
+                    *self.shared.route_position.lock().unwrap() =
+                        Some(route.position(bike.distance()));
+                }
+
+                metrics.update(readings.power(), readings.cadence(), readings.heart_rate());
+
+                if let Some(ref mut w_prime_balance) = w_prime_balance {
+                    w_prime_balance.update(readings.power().unwrap_or(0), 1.);
+                }
+
+                *self.shared.telemetry.lock().unwrap() = Some(Telemetry {
+                    elapsed: next_sample,
+                    power_3s: metrics.rolling_power(3),
+                    power_10s: metrics.rolling_power(10),
+                    power_30s: metrics.rolling_power(30),
+                    average_power: metrics.average_power(),
+                    normalized_power: metrics.normalized_power(),
+                    intensity_factor: metrics.intensity_factor(),
+                    training_stress: metrics.training_stress(),
+                    work: metrics.work(),
+                    average_cadence: metrics.average_cadence(),
+                    average_heart_rate: metrics.average_heart_rate(),
+                    w_prime_balance: w_prime_balance.map(|w| w.balance()),
+                    cadence: readings.cadence(),
+                    speed: readings.speed(),
+                    distance: recording.samples.last().and_then(|s| s.distance),
+                    gear: gear + 1,
+                });
+
+                next_sample += 1;
+            }
+
+            let (step_mode, set_point, step_grade, step_resistance) = match self.plan {
+                Plan::Workout(ref wko) => {
+                    let step = &wko.steps[step_index];
+                    (
+                        step.mode,
+                        step.set_point.try_into().unwrap(),
+                        step.target_grade,
+                        step.target_resistance,
+                    )
+                }
+                Plan::Route(ref route) => (
+                    StepMode::Simulation,
+                    0,
+                    Some(route.grade_at(bike.distance())),
+                    None,
+                ),
+                Plan::FreeRide(initial) => {
+                    let manual = control.manual_target.unwrap_or(initial);
+                    match manual.mode {
+                        StepMode::Erg => (
+                            StepMode::Erg,
+                            manual.value.clamp(0., u16::MAX as f64) as u16,
+                            None,
+                            None,
+                        ),
+                        StepMode::Simulation => (StepMode::Simulation, 0, Some(manual.value), None),
+                        StepMode::Resistance => (StepMode::Resistance, 0, None, Some(manual.value)),
+                    }
                 }
             };
 
             if step_changed {
-                let power: u16 = self.workout.steps[step_index].set_point.try_into().unwrap();
+                debug!("new step: {}, set point: {}", step_index, set_point);
+            }
+
+            let (mode, target_grade) = match control.grade_override {
+                Some(grade) => (StepMode::Simulation, Some(grade)),
+                None => (step_mode, step_grade),
+            };
+
+            gear = gearing.gear(control.gear.unwrap_or(gearing.initial_gear));
+
+            let set_point = match (control.heart_rate_target, mode) {
+                (Some(heart_rate_target), StepMode::Erg) => {
+                    let controller = heart_rate_controller.get_or_insert_with(|| {
+                        HeartRateController::new(self.config.heart_rate_control, set_point)
+                    });
+
+                    if sampled {
+                        let heart_rate = self.shared.readings.lock().unwrap().heart_rate();
+                        controller.update(heart_rate_target, heart_rate, 1.);
+                    }
+
+                    controller.power()
+                }
+                _ => {
+                    heart_rate_controller = None;
+                    set_point
+                }
+            };
 
-                debug!("new step: {}, set target power to {}", step_index, power);
+            // what the trainer can hold at its current speed, once it's
+            // reported its maximum resistance
+            let max_power = match *capabilities.read().unwrap() {
+                Some(capabilities) => self
+                    .shared
+                    .readings
+                    .lock()
+                    .unwrap()
+                    .speed_from(Source::Trainer)
+                    .map_or(MAX_TARGET_POWER, |speed| capabilities.max_power(speed)),
+                None => MAX_TARGET_POWER,
+            };
+
+            bike_grade = match mode {
+                StepMode::Simulation => target_grade.unwrap_or(0.),
+                _ => 0.,
+            };
 
-                sender.send(power).unwrap();
+            let power = match (w_prime_balance, w_prime_floor) {
+                (Some(w_prime_balance), Some(floor)) => {
+                    set_point.min(w_prime_balance.max_power(floor, 1.))
+                }
+                _ => set_point,
             }
+            .min(max_power);
 
-            {
-                let step_elapsed = (step_elapsed / 1000) as u32;
+            let cadence_action = match cadence_guard {
+                Some(ref mut cadence_guard) if mode == StepMode::Erg => {
+                    if sampled {
+                        cadence_guard.update(self.shared.readings.lock().unwrap().cadence());
+                    }
+                    cadence_guard.action()
+                }
+                Some(ref mut cadence_guard) => {
+                    cadence_guard.reset();
+                    None
+                }
+                None => None,
+            };
+
+            // the power meter can't be matched while the target is eased off
+            if sampled && mode == StepMode::Erg && cadence_action.is_none() {
+                if let Some(ref mut power_match) = power_match {
+                    let meter_power = self
+                        .shared
+                        .readings
+                        .lock()
+                        .unwrap()
+                        .power_from(Source::PowerMeter);
+                    power_match.update(power, meter_power);
+                }
+            }
+
+            let trainer_power = match power_match {
+                Some(ref power_match) => power_match.target(power).min(max_power),
+                None => power,
+            };
 
-                let mut status = self.status.lock().unwrap();
-                match &mut *status {
-                    Some(ref mut status) => {
-                        status.step_index = step_index;
-                        status.step_elapsed = step_elapsed;
+            let next_target = match mode {
+                // drop the target so the rider can start pedalling again
+                StepMode::Erg if paused => Target::Power(0),
+                StepMode::Simulation if paused => Target::Simulation(0.),
+                StepMode::Resistance if paused => Target::Resistance(0.),
+                StepMode::Erg => match cadence_action {
+                    Some(CadenceGuardAction::ReducePower(fraction)) => {
+                        Target::Power((trainer_power as f64 * fraction).round() as u16)
                     }
-                    None => {
-                        *status = Some(WorkoutStatus {
-                            step_index,
-                            step_elapsed,
-                        })
+                    Some(CadenceGuardAction::Resistance(resistance)) => {
+                        Target::Resistance(resistance)
                     }
+                    None => Target::Power(trainer_power),
+                },
+                StepMode::Simulation => {
+                    Target::Simulation(gearing.effective_grade(target_grade.unwrap_or(0.), gear))
                 }
+                StepMode::Resistance => Target::Resistance(step_resistance.unwrap_or(0.)),
+            };
+
+            if target != Some(next_target) {
+                debug!("setting target to {:?}", next_target);
+                sender.send(next_target).unwrap();
+                target = Some(next_target);
+            }
+
+            {
+                let step_elapsed = (step_elapsed / 1000) as u32;
+
+                let mut status = self.shared.status.lock().unwrap();
+                *status = Some(WorkoutStatus {
+                    step_index,
+                    step_elapsed,
+                    mode,
+                    target_power: if mode == StepMode::Erg { power } else { 0 },
+                    target_grade,
+                    target_limited: mode == StepMode::Erg && power != set_point,
+                    power_match_offset: power_match.as_ref().map(|p| p.offset()),
+                    heart_rate_target: heart_rate_controller
+                        .as_ref()
+                        .and(control.heart_rate_target),
+                    cadence_limited: cadence_action.is_some(),
+                    paused,
+                });
             }
 
             std::thread::sleep(Duration::from_millis(10));
         }
+
+        // disconnecting the control loop stops it and releases the trainer
+        // before another session can start its own
+        drop(sender);
+        if control_loop.join().is_err() {
+            error!("power control loop panicked");
+        }
+
+        recording
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use crate::executor::{CommandState, Target};
+
+    #[test]
+    fn it_acks_targets_by_command_page() {
+        let pending = |target| CommandState::Pending(target, Instant::now());
+
+        // target power statuses echo the power, in quarter watts
+        assert_eq!(
+            pending(Target::Power(150)).acknowledge(49, Some(800)),
+            CommandState::Acked(Target::Power(200))
+        );
+
+        let simulation = pending(Target::Simulation(2.5));
+        assert_eq!(simulation.acknowledge(49, None), simulation);
+        assert_eq!(simulation.acknowledge(48, None), simulation);
+        assert_eq!(
+            simulation.acknowledge(51, None),
+            CommandState::Acked(Target::Simulation(2.5))
+        );
+
+        let resistance = pending(Target::Resistance(30.));
+        assert_eq!(resistance.acknowledge(51, None), resistance);
+        assert_eq!(
+            resistance.acknowledge(48, None),
+            CommandState::Acked(Target::Resistance(30.))
+        );
+
+        assert_eq!(CommandState::None.acknowledge(51, None), CommandState::None);
+    }
+
+    #[test]
+    fn it_resends_commands_until_acked() {
+        let timeout = Duration::from_millis(500);
+        let target = Target::Resistance(30.);
+
+        assert!(CommandState::None.command_required(target, timeout));
+
+        let pending = CommandState::Pending(target, Instant::now());
+        assert!(!pending.command_required(target, timeout));
+        assert!(pending.command_required(Target::Resistance(40.), timeout));
+
+        let stale = CommandState::Pending(target, Instant::now() - Duration::from_secs(1));
+        assert!(stale.command_required(target, timeout));
+
+        let acked = CommandState::Acked(target);
+        assert!(!acked.command_required(target, timeout));
+        assert!(acked.command_required(Target::Simulation(0.), timeout));
     }
 }
@@ -0,0 +1,118 @@
+use ts_rs::TS;
+
+use crate::config;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize, TS)]
+#[ts(export, export_to = "../src/types/HeartRateTarget.ts")]
+pub struct HeartRateTarget {
+    // beats per minute
+    pub low: u8,
+    pub high: u8,
+}
+
+// Steers the ERG target to hold heart rate inside a band. Updated once per
+// second; heart rate lags power by a minute or more, so the correction is
+// mostly integral and the target only moves at a limited rate.
+pub struct HeartRateController {
+    config: config::HeartRateControl,
+    base: f64,
+    integral: f64,
+    power: f64,
+}
+
+impl HeartRateController {
+    // starts from the power the rider was already holding
+    pub fn new(config: config::HeartRateControl, power: u16) -> HeartRateController {
+        let power = (power as f64).clamp(config.min_power as f64, config.max_power as f64);
+
+        HeartRateController {
+            config,
+            base: power,
+            integral: 0.,
+            power,
+        }
+    }
+
+    pub fn update(&mut self, target: HeartRateTarget, heart_rate: Option<u8>, dt: f64) {
+        let (min, max) = (self.config.min_power as f64, self.config.max_power as f64);
+        let max_step = self.config.max_ramp * dt;
+
+        let heart_rate = match heart_rate {
+            Some(heart_rate) => heart_rate as f64,
+            None => {
+                // without heart rate there's nothing to stop the target running
+                // away, so ease off to the minimum and start over from there
+                self.power = (self.power - max_step).max(min);
+                self.base = self.power;
+                self.integral = 0.;
+                return;
+            }
+        };
+
+        // no correction inside the band, otherwise towards its nearest edge
+        let error = if heart_rate < target.low as f64 {
+            target.low as f64 - heart_rate
+        } else if heart_rate > target.high as f64 {
+            target.high as f64 - heart_rate
+        } else {
+            0.
+        };
+
+        let integral = self.integral + error * dt;
+        let output = self.base
+            + self.config.proportional_gain * error
+            + self.config.integral_gain * integral;
+
+        // stop integrating while pinned at a bound so it can come straight back
+        if (min..=max).contains(&output) {
+            self.integral = integral;
+        }
+
+        self.power += (output.clamp(min, max) - self.power).clamp(-max_step, max_step);
+    }
+
+    pub fn power(&self) -> u16 {
+        self.power.round() as u16
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::config;
+    use crate::heart_rate_control::{HeartRateController, HeartRateTarget};
+
+    #[test]
+    fn it_holds_heart_rate_in_the_band() {
+        let config = config::HeartRateControl::default();
+        let target = HeartRateTarget {
+            low: 140,
+            high: 145,
+        };
+        let mut controller = HeartRateController::new(config, 150);
+
+        // heart rate settles at 60 + 0.4 bpm/W with a 30 s time constant
+        let mut heart_rate: f64 = 100.;
+        let mut last_power = controller.power();
+        for _ in 0..1800 {
+            controller.update(target, Some(heart_rate.round() as u8), 1.);
+
+            let power = controller.power();
+            assert!((power as f64 - last_power as f64).abs() <= config.max_ramp + 1.);
+            last_power = power;
+
+            heart_rate += (60. + 0.4 * power as f64 - heart_rate) / 30.;
+        }
+
+        assert!(
+            (139. ..=146.).contains(&heart_rate),
+            "heart rate: {}",
+            heart_rate
+        );
+
+        // a lost strap ramps down to the floor
+        for _ in 0..1800 {
+            controller.update(target, None, 1.);
+        }
+        assert_eq!(controller.power(), config.min_power);
+    }
+}
@@ -0,0 +1,540 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use log::warn;
+use ts_rs::TS;
+
+use crate::metrics;
+use crate::power_curve::{PowerCurve, PowerCurves};
+use crate::recorder::Recording;
+
+const INDEX_FILE: &str = "index.json";
+const POWER_CURVES_FILE: &str = "power_curves.json";
+
+// rides starting within this many seconds of each other are considered to be
+// recordings of the same session
+const DUPLICATE_TOLERANCE: u64 = 60;
+
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize, TS)]
+#[ts(export, export_to = "../src/types/Sample.ts")]
+pub struct Sample {
+    pub elapsed: u32,
+    pub power: Option<u16>,
+    pub cadence: Option<u8>,
+    pub heart_rate: Option<u8>,
+    // metres per second
+    pub speed: Option<f64>,
+    // metres
+    pub distance: Option<f64>,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize, TS)]
+#[ts(export, export_to = "../src/types/RideSummary.ts")]
+pub struct RideSummary {
+    #[ts(type = "number")]
+    pub id: u64,
+    pub title: String,
+    #[ts(type = "number")]
+    pub start_time: u64,
+    pub duration: u32,
+    pub average_power: u16,
+    pub maximum_power: u16,
+    pub normalized_power: u16,
+    pub average_cadence: Option<u8>,
+    pub average_heart_rate: Option<u8>,
+    pub maximum_heart_rate: Option<u8>,
+    pub work: u32,
+    pub intensity_factor: Option<f64>,
+    pub training_stress: Option<f64>,
+    pub distance: Option<f64>,
+    // the trainer asked for calibration during the ride, so its power may be off
+    #[serde(default)]
+    pub calibration_required: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize, TS)]
+#[ts(export, export_to = "../src/types/Ride.ts")]
+pub struct Ride {
+    pub summary: RideSummary,
+    pub samples: Vec<Sample>,
+    #[serde(default)]
+    pub laps: Vec<Lap>,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize, TS)]
+#[ts(export, export_to = "../src/types/Lap.ts")]
+pub struct Lap {
+    // seconds from the start of the ride
+    pub start: u32,
+    pub duration: u32,
+    pub average_power: u16,
+}
+
+#[derive(Clone, Debug, Default, serde::Deserialize, TS)]
+#[ts(export, export_to = "../src/types/RideQuery.ts")]
+pub struct RideQuery {
+    #[ts(type = "number | null")]
+    pub from: Option<u64>,
+    #[ts(type = "number | null")]
+    pub to: Option<u64>,
+    pub title: Option<String>,
+}
+
+impl Ride {
+    pub fn new(title: String, recording: Recording, ftp: Option<u16>) -> Ride {
+        let start_time = recording
+            .start_time
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+
+        let mut ride = Ride::from_samples(start_time, title, recording.samples, ftp);
+        ride.summary.calibration_required = recording.calibration_required;
+
+        ride
+    }
+
+    pub fn from_samples(
+        start_time: u64,
+        title: String,
+        samples: Vec<Sample>,
+        ftp: Option<u16>,
+    ) -> Ride {
+        Ride {
+            summary: RideSummary::new(start_time, title, &samples, ftp),
+            samples,
+            laps: vec![],
+        }
+    }
+}
+
+impl Lap {
+    pub fn new(start: u32, duration: u32, samples: &[Sample]) -> Lap {
+        let power = samples
+            .iter()
+            .filter(|s| s.elapsed >= start && s.elapsed < start + duration)
+            .map(|s| s.power.unwrap_or(0))
+            .collect::<Vec<_>>();
+
+        Lap {
+            start,
+            duration,
+            average_power: metrics::average_power(&power).round() as u16,
+        }
+    }
+}
+
+impl RideSummary {
+    pub fn new(
+        start_time: u64,
+        title: String,
+        samples: &[Sample],
+        ftp: Option<u16>,
+    ) -> RideSummary {
+        let power = power_series(samples);
+        let cadence = samples
+            .iter()
+            .filter_map(|s| s.cadence.filter(|&c| c > 0))
+            .collect::<Vec<_>>();
+        let heart_rate = samples
+            .iter()
+            .filter_map(|s| s.heart_rate)
+            .collect::<Vec<_>>();
+
+        let duration = samples.len() as u32;
+        let normalized_power = metrics::segmented_normalized_power(&power_segments(samples));
+
+        RideSummary {
+            id: start_time,
+            title,
+            start_time,
+            duration,
+            average_power: metrics::average_power(&power).round() as u16,
+            maximum_power: power.iter().copied().max().unwrap_or(0),
+            normalized_power: normalized_power.round() as u16,
+            average_cadence: average(&cadence),
+            average_heart_rate: average(&heart_rate),
+            maximum_heart_rate: heart_rate.iter().copied().max(),
+            work: (power.iter().map(|&p| p as u32).sum::<u32>() as f64 / 1000.).round() as u32,
+            intensity_factor: ftp.map(|ftp| metrics::intensity_factor(normalized_power, ftp)),
+            training_stress: ftp
+                .map(|ftp| metrics::training_stress(duration, normalized_power, ftp)),
+            distance: samples.iter().filter_map(|s| s.distance).reduce(f64::max),
+            calibration_required: false,
+        }
+    }
+}
+
+pub fn power_series(samples: &[Sample]) -> Vec<u16> {
+    samples.iter().map(|s| s.power.unwrap_or(0)).collect()
+}
+
+// Power split wherever samples are missing, like the pauses in an imported
+// activity, so rolling windows don't join the data on either side.
+pub fn power_segments(samples: &[Sample]) -> Vec<Vec<u16>> {
+    let mut segments: Vec<Vec<u16>> = vec![];
+    let mut last: Option<u32> = None;
+
+    for sample in samples {
+        let power = sample.power.unwrap_or(0);
+        match segments.last_mut() {
+            Some(segment) if last.is_some_and(|last| sample.elapsed <= last + 1) => {
+                segment.push(power)
+            }
+            _ => segments.push(vec![power]),
+        }
+        last = Some(sample.elapsed);
+    }
+
+    segments
+}
+
+fn average(values: &[u8]) -> Option<u8> {
+    if values.is_empty() {
+        None
+    } else {
+        let sum = values.iter().map(|&v| v as u32).sum::<u32>();
+        Some((sum as f64 / values.len() as f64).round() as u8)
+    }
+}
+
+pub struct History {
+    dir: PathBuf,
+    index: Vec<RideSummary>,
+    power_curves: PowerCurves,
+}
+
+impl History {
+    pub fn open(dir: &Path) -> Result<History, String> {
+        fs::create_dir_all(dir).map_err(|e| format!("create history directory: {}", e))?;
+
+        let index_path = dir.join(INDEX_FILE);
+        let index = if index_path.exists() {
+            read_json(&index_path)?
+        } else {
+            rebuild_index(dir)?
+        };
+
+        let mut history = History {
+            dir: dir.into(),
+            index,
+            power_curves: PowerCurves::default(),
+        };
+        history.sort_index();
+
+        let power_curves_path = dir.join(POWER_CURVES_FILE);
+        if power_curves_path.exists() {
+            history.power_curves = read_json(&power_curves_path)?;
+        } else {
+            history.rebuild_power_curves()?;
+        }
+
+        Ok(history)
+    }
+
+    pub fn list(&self, query: &RideQuery) -> Vec<RideSummary> {
+        let title = query.title.as_ref().map(|t| t.to_lowercase());
+
+        self.index
+            .iter()
+            .filter(|ride| query.from.is_none_or(|from| ride.start_time >= from))
+            .filter(|ride| query.to.is_none_or(|to| ride.start_time < to))
+            .filter(|ride| {
+                title
+                    .as_ref()
+                    .is_none_or(|title| ride.title.to_lowercase().contains(title))
+            })
+            .cloned()
+            .collect()
+    }
+
+    pub fn has_ride_near(&self, start_time: u64) -> bool {
+        self.index
+            .iter()
+            .any(|ride| ride.start_time.abs_diff(start_time) <= DUPLICATE_TOLERANCE)
+    }
+
+    pub fn get(&self, id: u64) -> Result<Ride, String> {
+        if !self.index.iter().any(|ride| ride.id == id) {
+            return Err(format!("no ride with id {}", id));
+        }
+
+        read_json(&self.ride_path(id))
+    }
+
+    pub fn save(&mut self, ride: &Ride) -> Result<(), String> {
+        if self.index.iter().any(|r| r.id == ride.summary.id) {
+            return Err(format!("ride with id {} already exists", ride.summary.id));
+        }
+
+        write_json(&self.ride_path(ride.summary.id), ride)?;
+
+        self.index.push(ride.summary.clone());
+        self.sort_index();
+        self.write_index()?;
+
+        self.power_curves.insert(
+            ride.summary.id,
+            ride.summary.start_time,
+            &power_segments(&ride.samples),
+        );
+        self.write_power_curves()
+    }
+
+    pub fn delete(&mut self, id: u64) -> Result<(), String> {
+        let position = self
+            .index
+            .iter()
+            .position(|ride| ride.id == id)
+            .ok_or_else(|| format!("no ride with id {}", id))?;
+
+        fs::remove_file(self.ride_path(id)).map_err(|e| format!("remove ride file: {}", e))?;
+
+        self.index.remove(position);
+        self.write_index()?;
+
+        self.power_curves.remove(id);
+        self.write_power_curves()
+    }
+
+    pub fn ride_power_curve(&self, id: u64) -> Result<PowerCurve, String> {
+        self.power_curves
+            .ride(id)
+            .ok_or_else(|| format!("no ride with id {}", id))
+    }
+
+    pub fn power_curve(&self, since: Option<u64>) -> PowerCurve {
+        match since {
+            Some(since) => self.power_curves.best_since(Some(since)),
+            None => self.power_curves.best.clone(),
+        }
+    }
+
+    fn ride_path(&self, id: u64) -> PathBuf {
+        self.dir.join(format!("{}.json", id))
+    }
+
+    fn sort_index(&mut self) {
+        self.index
+            .sort_by_key(|ride| std::cmp::Reverse(ride.start_time));
+    }
+
+    fn write_index(&self) -> Result<(), String> {
+        write_json(&self.dir.join(INDEX_FILE), &self.index)
+    }
+
+    fn rebuild_power_curves(&mut self) -> Result<(), String> {
+        let mut power_curves = PowerCurves::default();
+
+        for summary in self.index.iter() {
+            let ride = self.get(summary.id)?;
+            power_curves.insert(
+                summary.id,
+                summary.start_time,
+                &power_segments(&ride.samples),
+            );
+        }
+
+        self.power_curves = power_curves;
+        self.write_power_curves()
+    }
+
+    fn write_power_curves(&self) -> Result<(), String> {
+        write_json(&self.dir.join(POWER_CURVES_FILE), &self.power_curves)
+    }
+}
+
+fn rebuild_index(dir: &Path) -> Result<Vec<RideSummary>, String> {
+    let entries = fs::read_dir(dir).map_err(|e| format!("read history directory: {}", e))?;
+
+    let mut index = vec![];
+
+    for entry in entries {
+        let path = entry
+            .map_err(|e| format!("read history directory: {}", e))?
+            .path();
+
+        if path.extension().is_none_or(|ext| ext != "json")
+            || path
+                .file_name()
+                .is_some_and(|name| name == INDEX_FILE || name == POWER_CURVES_FILE)
+        {
+            continue;
+        }
+
+        match read_json::<Ride>(&path) {
+            Ok(ride) => index.push(ride.summary),
+            Err(e) => warn!("skipping {}: {}", path.display(), e),
+        }
+    }
+
+    Ok(index)
+}
+
+fn read_json<T: serde::de::DeserializeOwned>(path: &Path) -> Result<T, String> {
+    let data = fs::read(path).map_err(|e| format!("read {}: {}", path.display(), e))?;
+
+    serde_json::from_slice(&data).map_err(|e| format!("parse {}: {}", path.display(), e))
+}
+
+fn write_json<T: serde::Serialize + ?Sized>(path: &Path, value: &T) -> Result<(), String> {
+    let data = serde_json::to_vec(value).map_err(|e| format!("serialize: {}", e))?;
+
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, data).map_err(|e| format!("write {}: {}", tmp_path.display(), e))?;
+    fs::rename(&tmp_path, path).map_err(|e| format!("rename {}: {}", tmp_path.display(), e))
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use crate::history::{
+        power_segments, History, Ride, RideQuery, Sample, INDEX_FILE, POWER_CURVES_FILE,
+    };
+
+    fn history_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("ergmode-history-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn ride(start_time: u64, title: &str) -> Ride {
+        let samples = (0..60)
+            .map(|elapsed| Sample {
+                elapsed,
+                power: Some(200 + elapsed as u16),
+                cadence: Some(90),
+                heart_rate: None,
+                speed: Some(8.),
+                distance: Some(elapsed as f64 * 8.),
+            })
+            .collect();
+
+        Ride::from_samples(start_time, title.into(), samples, None)
+    }
+
+    #[test]
+    fn it_saves_and_loads_rides() {
+        let dir = history_dir("round-trip");
+        let first = ride(1_700_000_000, "Sweet spot");
+        let second = ride(1_700_100_000, "Recovery");
+
+        {
+            let mut history = History::open(&dir).expect("history opens");
+            history.save(&first).expect("ride saves");
+            history.save(&second).expect("ride saves");
+
+            assert!(history.save(&first).is_err(), "duplicate id saved");
+        }
+
+        let mut history = History::open(&dir).expect("history reopens");
+        assert_eq!(history.get(first.summary.id), Ok(first.clone()));
+
+        let all = history.list(&RideQuery::default());
+        assert_eq!(all, vec![second.summary.clone(), first.summary.clone()]);
+
+        history.delete(second.summary.id).expect("ride deletes");
+        assert!(history.get(second.summary.id).is_err());
+        assert!(history.delete(second.summary.id).is_err());
+        assert_eq!(
+            History::open(&dir)
+                .expect("history reopens")
+                .list(&RideQuery::default()),
+            vec![first.summary.clone()]
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn it_filters_rides_by_date_and_title() {
+        let dir = history_dir("filter");
+        let mut history = History::open(&dir).expect("history opens");
+        for (start_time, title) in [
+            (1000, "Sweet spot"),
+            (2000, "Recovery"),
+            (3000, "Sweet Spot 2"),
+        ] {
+            history.save(&ride(start_time, title)).expect("ride saves");
+        }
+
+        let ids = |query: RideQuery| {
+            history
+                .list(&query)
+                .iter()
+                .map(|ride| ride.id)
+                .collect::<Vec<_>>()
+        };
+
+        // the range includes its start but not its end
+        assert_eq!(
+            ids(RideQuery {
+                from: Some(2000),
+                to: Some(3000),
+                title: None,
+            }),
+            vec![2000]
+        );
+        assert_eq!(
+            ids(RideQuery {
+                from: None,
+                to: None,
+                title: Some("sweet".into()),
+            }),
+            vec![3000, 1000]
+        );
+        assert_eq!(
+            ids(RideQuery {
+                from: Some(2000),
+                to: None,
+                title: Some("SPOT".into()),
+            }),
+            vec![3000]
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn it_rebuilds_a_missing_index() {
+        let dir = history_dir("rebuild");
+        let first = ride(1000, "First");
+        let second = ride(2000, "Second");
+
+        {
+            let mut history = History::open(&dir).expect("history opens");
+            history.save(&first).expect("ride saves");
+            history.save(&second).expect("ride saves");
+        }
+
+        fs::remove_file(dir.join(INDEX_FILE)).unwrap();
+        fs::remove_file(dir.join(POWER_CURVES_FILE)).unwrap();
+        fs::write(dir.join("notes.json"), "not a ride").unwrap();
+
+        let history = History::open(&dir).expect("history reopens");
+        assert_eq!(
+            history.list(&RideQuery::default()),
+            vec![second.summary.clone(), first.summary.clone()]
+        );
+        assert!(history.ride_power_curve(first.summary.id).is_ok());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn it_splits_power_at_gaps() {
+        let mut samples = ride(1000, "Paused").samples;
+        for sample in samples.iter_mut().skip(30) {
+            sample.elapsed += 600;
+        }
+
+        let segments = power_segments(&samples);
+        assert_eq!(segments.len(), 2);
+        assert_eq!(segments[0].len(), 30);
+        assert_eq!(segments[1][0], 230);
+    }
+}
@@ -1,12 +1,31 @@
 // Prevents additional console window on Windows in release, DO NOT REMOVE!!
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
+mod activity;
+mod auto_pause;
+mod cadence_guard;
+mod calibration;
 mod config;
+mod critical_power;
 mod executor;
+mod heart_rate_control;
+mod history;
+mod metrics;
+mod physics;
+mod power_curve;
+mod power_match;
+mod power_meter;
+mod recorder;
+mod route;
+mod scan;
+mod speed_cadence;
+mod trainer_data;
+mod training_load;
+mod w_prime;
 mod workout;
 
 use antrs::node;
-use antrs::profile::{fitness_equipment, heart_rate_monitor};
+use antrs::profile::{fitness_equipment, generic, heart_rate_monitor};
 use log::{debug, error, info, trace, warn};
 use std::sync::Arc;
 use std::sync::Mutex;
@@ -18,19 +37,33 @@ use workout::Workout;
 
 include!(concat!(env!("OUT_DIR"), "/ant_network_key.rs"));
 
+const CONFIG_FILE: &str = "appconfig.toml";
+
+// trainer capabilities are asked for this many times, this far apart
+const CAPABILITIES_REQUEST_ATTEMPTS: u32 = 5;
+const CAPABILITIES_REQUEST_INTERVAL: Duration = Duration::from_secs(1);
+
 #[derive(Clone)]
 pub struct FitnessEquipmentState {
     pub channel: u8,
     pub receiver: crossbeam_channel::Receiver<fitness_equipment::CommandStatusData>,
+    pub capabilities: Arc<RwLock<Option<trainer_data::Capabilities>>>,
 }
 
 struct AppState {
-    config: config::AppConfig,
+    config: RwLock<config::AppConfig>,
     node: Arc<Mutex<Option<node::Node>>>,
     hrm_channel: RwLock<Option<u8>>,
     fe_state: RwLock<Option<FitnessEquipmentState>>,
     workout: Mutex<Option<Workout>>,
+    route: Mutex<Option<route::Route>>,
     workout_status: Arc<Mutex<Option<executor::WorkoutStatus>>>,
+    telemetry: Arc<Mutex<Option<executor::Telemetry>>>,
+    readings: Arc<Mutex<recorder::Readings>>,
+    control: Arc<Mutex<executor::Control>>,
+    route_position: Arc<Mutex<Option<route::RoutePosition>>>,
+    history: Mutex<history::History>,
+    scanning: Mutex<bool>,
 }
 
 #[derive(Clone, serde::Serialize, TS)]
@@ -78,6 +111,71 @@ fn open_node(state: State<'_, Arc<AppState>>, window: Window) {
     });
 }
 
+// Assigns a channel searching for each paired device of a role. If one fails
+// the channels already assigned are closed again.
+fn assign_candidates<T>(
+    node: &mut node::Node,
+    pairings: &[config::DevicePairing],
+    assign: impl Fn(&mut node::Node, config::DevicePairing) -> Result<(u8, T), node::Error>,
+) -> Result<Vec<(u8, T)>, String> {
+    let mut candidates = vec![];
+
+    for &pairing in pairings {
+        match assign(node, pairing) {
+            Ok(candidate) => candidates.push(candidate),
+            Err(e) => {
+                for (channel, _) in candidates {
+                    if let Err(e) = node.close_channel(channel) {
+                        error!("closing channel {}: {}", channel, e);
+                    }
+                }
+                return Err(format!("assigning channel: {}", e));
+            }
+        }
+    }
+
+    Ok(candidates)
+}
+
+// Blocks until one of the candidate channels receives data, closes the others
+// and returns the responding channel with its first message and receiver.
+fn first_responding<T>(
+    node: &Mutex<Option<node::Node>>,
+    mut candidates: Vec<(u8, crossbeam_channel::Receiver<T>)>,
+) -> Option<(u8, T, crossbeam_channel::Receiver<T>)> {
+    while !candidates.is_empty() {
+        let mut select = crossbeam_channel::Select::new();
+        for (_, receiver) in candidates.iter() {
+            select.recv(receiver);
+        }
+
+        let operation = select.select();
+        let index = operation.index();
+
+        match operation.recv(&candidates[index].1) {
+            Ok(message) => {
+                let (channel, receiver) = candidates.swap_remove(index);
+
+                let mut node = node.lock().unwrap();
+                if let Some(ref mut node) = *node {
+                    for (other, _) in candidates.iter() {
+                        if let Err(e) = node.close_channel(*other) {
+                            error!("closing channel {}: {}", other, e);
+                        }
+                    }
+                }
+
+                return Some((channel, message, receiver));
+            }
+            Err(_) => {
+                candidates.swap_remove(index);
+            }
+        }
+    }
+
+    None
+}
+
 #[tauri::command]
 async fn open_hrm(state: State<'_, Arc<AppState>>, window: Window) -> Result<(), String> {
     debug!("opening heart rate monitor");
@@ -85,20 +183,53 @@ async fn open_hrm(state: State<'_, Arc<AppState>>, window: Window) -> Result<(),
     let mut nd = state.node.lock().unwrap();
 
     if let Some(ref mut nd) = *nd {
-        let (hrm, receiver) =
-            heart_rate_monitor::new_paired(state.config.devices.heart_rate_monitor.into());
-        let channel = nd
-            .assign_channel(
+        let pairings = state
+            .config
+            .read()
+            .unwrap()
+            .devices
+            .heart_rate_monitor
+            .clone();
+        if pairings.is_empty() {
+            info!("no heart rate monitor paired");
+            return Ok(());
+        }
+
+        let candidates = assign_candidates(nd, &pairings, |node, pairing| {
+            let (hrm, receiver) = heart_rate_monitor::new_paired(pairing.into());
+            let channel = node.assign_channel(
                 Box::new(hrm),
                 Some(node::ChannelOptions {
                     low_priority_search_timeout: Some(255),
                     search_timeout: Some(0),
                 }),
-            )
-            .map_err(|e| format!("assigning channel: {}", e))?;
+            )?;
+
+            Ok((channel, receiver))
+        })?;
+
+        let state = Arc::clone(&state);
 
         std::thread::spawn(move || {
-            for message in receiver.iter() {
+            let (channel, first, receiver) = match first_responding(&state.node, candidates) {
+                Some(responding) => responding,
+                None => return,
+            };
+
+            debug!("heart rate monitor connected on channel {}", channel);
+
+            {
+                let mut hrm_channel = state.hrm_channel.write().unwrap();
+                *hrm_channel = Some(channel);
+            }
+
+            for message in std::iter::once(first).chain(receiver.iter()) {
+                state
+                    .readings
+                    .lock()
+                    .unwrap()
+                    .set_heart_rate(message.computed_heart_rate);
+
                 window
                     .emit(
                         "heart_rate",
@@ -114,11 +245,6 @@ async fn open_hrm(state: State<'_, Arc<AppState>>, window: Window) -> Result<(),
             }
         });
 
-        {
-            let mut hrm_channel = state.hrm_channel.write().unwrap();
-            *hrm_channel = Some(channel);
-        }
-
         Ok(())
     } else {
         Err("node not open".into())
@@ -135,32 +261,112 @@ async fn open_fitness_equipment(
     let mut node = state.node.lock().unwrap();
 
     if let Some(ref mut node) = *node {
-        let (fe, receiver) =
-            fitness_equipment::new_paired(state.config.devices.fitness_equipment.into());
-        let channel = node
-            .assign_channel(
+        let pairings = state
+            .config
+            .read()
+            .unwrap()
+            .devices
+            .fitness_equipment
+            .clone();
+        if pairings.is_empty() {
+            info!("no fitness equipment paired");
+            return Ok(());
+        }
+
+        let candidates = assign_candidates(node, &pairings, |node, pairing| {
+            let (fe, receiver) = fitness_equipment::new_paired(pairing.into());
+            let channel = node.assign_channel(
                 Box::new(fe),
                 Some(node::ChannelOptions {
                     low_priority_search_timeout: Some(255),
                     search_timeout: Some(0),
                 }),
-            )
-            .map_err(|e| format!("assigning channel: {}", e))?;
+            )?;
 
-        let (s, r) = crossbeam_channel::unbounded();
+            Ok((channel, receiver))
+        })?;
 
-        {
-            let mut fe_state = state.fe_state.write().unwrap();
-            *fe_state = Some(FitnessEquipmentState {
-                channel,
-                receiver: r,
-            });
-        }
+        let state = Arc::clone(&state);
 
         std::thread::spawn(move || {
-            for message in receiver.iter() {
+            let (channel, first, receiver) = match first_responding(&state.node, candidates) {
+                Some(responding) => responding,
+                None => return,
+            };
+
+            debug!("fitness equipment connected on channel {}", channel);
+
+            let (s, r) = crossbeam_channel::unbounded();
+            let capabilities = Arc::new(RwLock::new(None));
+
+            {
+                let mut fe_state = state.fe_state.write().unwrap();
+                *fe_state = Some(FitnessEquipmentState {
+                    channel,
+                    receiver: r,
+                    capabilities: Arc::clone(&capabilities),
+                });
+            }
+
+            {
+                let state = Arc::clone(&state);
+                let capabilities = Arc::clone(&capabilities);
+
+                // the request can be missed, so keep asking until the page arrives
+                std::thread::spawn(move || {
+                    for _ in 0..CAPABILITIES_REQUEST_ATTEMPTS {
+                        if capabilities.read().unwrap().is_some() {
+                            return;
+                        }
+
+                        if let Some(ref node) = *state.node.lock().unwrap() {
+                            if let Err(e) = node.write_message(
+                                antrs::message::request_data_page(
+                                    channel,
+                                    trainer_data::PAGE_CAPABILITIES,
+                                ),
+                                Duration::from_millis(100),
+                            ) {
+                                error!("requesting trainer capabilities: {}", e);
+                            }
+                        }
+
+                        std::thread::sleep(CAPABILITIES_REQUEST_INTERVAL);
+                    }
+
+                    if capabilities.read().unwrap().is_none() {
+                        warn!("trainer did not report its capabilities");
+                    }
+                });
+            }
+
+            if let Some(ref node) = *state.node.lock().unwrap() {
+                let config = state.config.read().unwrap();
+                if let Some(rider) = config.rider {
+                    if let Err(e) =
+                        send_user_configuration(node, channel, &rider, config.wheel_circumference)
+                    {
+                        error!("{}", e);
+                    }
+                }
+            }
+
+            let mut decoder = trainer_data::TrainerDataDecoder::default();
+
+            for message in std::iter::once(first).chain(receiver.iter()) {
                 match message {
                     fitness_equipment::FitnessEquipmentData::StationaryBike(data) => {
+                        let update = decoder.decode_trainer_status(data.trainer_status);
+
+                        {
+                            let mut readings = state.readings.lock().unwrap();
+                            readings.set_power(config::Source::Trainer, data.instantaneous_power);
+                            readings.set_cadence(config::Source::Trainer, data.cadence);
+                            readings.set_calibration_required(update.calibration_required());
+                        }
+
+                        window.emit("trainer_data", update).unwrap();
+
                         window
                             .emit(
                                 "fitness_equipment_data",
@@ -171,11 +377,43 @@ async fn open_fitness_equipment(
                             )
                             .unwrap();
                     }
+                    fitness_equipment::FitnessEquipmentData::CommandStatus(data)
+                        if data.command_id == trainer_data::PAGE_USER_CONFIGURATION =>
+                    {
+                        let accepted = data.command_status == antrs::message::CommandStatus::Pass;
+                        if !accepted {
+                            warn!("user configuration not accepted: {:?}", data.command_status);
+                        }
+
+                        window.emit("user_configuration_status", accepted).unwrap();
+                    }
                     fitness_equipment::FitnessEquipmentData::CommandStatus(data) => {
                         s.send(data)
                             .expect("send to control loop channel should succeed");
                     }
-                    _ => {}
+                    fitness_equipment::FitnessEquipmentData::Other(payload) => {
+                        if let Some(progress) = calibration::decode_progress(&payload) {
+                            window.emit("calibration_progress", progress).unwrap();
+                        } else if let Some(result) = calibration::decode_result(&payload) {
+                            save_calibration(&state, result);
+                            window.emit("calibration_result", result).unwrap();
+                        } else if let Some(decoded) = trainer_data::decode_capabilities(&payload) {
+                            info!("trainer capabilities: {:?}", decoded);
+                            *capabilities.write().unwrap() = Some(decoded);
+                            window.emit("trainer_capabilities", decoded).unwrap();
+                        } else if let Some(update) = decoder.decode_general(&payload) {
+                            {
+                                let mut readings = state.readings.lock().unwrap();
+                                readings.set_speed(config::Source::Trainer, update.speed);
+                                readings
+                                    .set_distance(config::Source::Trainer, Some(update.distance));
+                            }
+
+                            window.emit("trainer_data", update).unwrap();
+                        } else {
+                            trace!("unhandled fitness equipment page: {:?}", payload);
+                        }
+                    }
                 }
             }
         });
@@ -186,6 +424,247 @@ async fn open_fitness_equipment(
     }
 }
 
+#[tauri::command]
+async fn open_power_meter(state: State<'_, Arc<AppState>>, window: Window) -> Result<(), String> {
+    debug!("opening power meter");
+
+    let mut node = state.node.lock().unwrap();
+
+    if let Some(ref mut node) = *node {
+        let pairings = state.config.read().unwrap().devices.power_meter.clone();
+        if pairings.is_empty() {
+            info!("no power meter paired");
+            return Ok(());
+        }
+        let kind = scan::DeviceKind::BicyclePower;
+
+        let candidates = assign_candidates(node, &pairings, |node, pairing| {
+            let (pm, receiver) =
+                generic::new_paired(kind.device_type(), kind.channel_period(), pairing.into());
+            let channel = node.assign_channel(
+                Box::new(pm),
+                Some(node::ChannelOptions {
+                    low_priority_search_timeout: Some(255),
+                    search_timeout: Some(0),
+                }),
+            )?;
+
+            Ok((channel, receiver))
+        })?;
+
+        let state = Arc::clone(&state);
+
+        std::thread::spawn(move || {
+            let (channel, first, receiver) = match first_responding(&state.node, candidates) {
+                Some(responding) => responding,
+                None => return,
+            };
+
+            debug!("power meter connected on channel {}", channel);
+
+            let mut decoder = power_meter::PowerMeterDecoder::default();
+
+            for payload in std::iter::once(first).chain(receiver.iter()) {
+                if let Some(update) = decoder.decode(&payload) {
+                    {
+                        let mut readings = state.readings.lock().unwrap();
+                        readings.set_power(config::Source::PowerMeter, update.power);
+                        readings.set_cadence(config::Source::PowerMeter, update.cadence);
+                    }
+
+                    window.emit("power_meter_data", update).unwrap();
+                }
+            }
+        });
+
+        Ok(())
+    } else {
+        Err("node not open".into())
+    }
+}
+
+#[tauri::command]
+async fn open_speed_cadence(
+    state: State<'_, Arc<AppState>>,
+    window: Window,
+    kind: scan::DeviceKind,
+) -> Result<(), String> {
+    debug!("opening {:?} sensor", kind);
+
+    let mut node = state.node.lock().unwrap();
+
+    if let Some(ref mut node) = *node {
+        let (pairings, wheel_circumference) = {
+            let config = state.config.read().unwrap();
+            let pairings = match kind {
+                scan::DeviceKind::BikeSpeedCadence => &config.devices.speed_cadence_sensor,
+                scan::DeviceKind::BikeSpeed => &config.devices.speed_sensor,
+                scan::DeviceKind::BikeCadence => &config.devices.cadence_sensor,
+                _ => return Err(format!("{:?} is not a speed or cadence sensor", kind)),
+            };
+            (pairings.clone(), config.wheel_circumference)
+        };
+        if pairings.is_empty() {
+            info!("no {:?} sensor paired", kind);
+            return Ok(());
+        }
+
+        let candidates = assign_candidates(node, &pairings, |node, pairing| {
+            let (sensor, receiver) =
+                generic::new_paired(kind.device_type(), kind.channel_period(), pairing.into());
+            let channel = node.assign_channel(
+                Box::new(sensor),
+                Some(node::ChannelOptions {
+                    low_priority_search_timeout: Some(255),
+                    search_timeout: Some(0),
+                }),
+            )?;
+
+            Ok((channel, receiver))
+        })?;
+
+        let state = Arc::clone(&state);
+
+        std::thread::spawn(move || {
+            let (channel, first, receiver) = match first_responding(&state.node, candidates) {
+                Some(responding) => responding,
+                None => return,
+            };
+
+            debug!("{:?} sensor connected on channel {}", kind, channel);
+
+            let mut decoder = speed_cadence::SpeedCadenceDecoder::new(kind, wheel_circumference);
+
+            for payload in std::iter::once(first).chain(receiver.iter()) {
+                let update = decoder.decode(&payload);
+
+                {
+                    let mut readings = state.readings.lock().unwrap();
+                    if kind != scan::DeviceKind::BikeCadence {
+                        readings.set_speed(config::Source::SpeedCadence, update.speed);
+                    }
+                    if kind != scan::DeviceKind::BikeSpeed {
+                        readings.set_cadence(config::Source::SpeedCadence, update.cadence);
+                    }
+                }
+
+                window.emit("speed_cadence_data", update).unwrap();
+            }
+        });
+
+        Ok(())
+    } else {
+        Err("node not open".into())
+    }
+}
+
+fn send_user_configuration(
+    node: &node::Node,
+    channel: u8,
+    rider: &config::RiderProfile,
+    wheel_circumference: f64,
+) -> Result<(), String> {
+    debug!("sending user configuration: {:?}", rider);
+
+    node.write_message(
+        antrs::message::acknowledged_data(
+            channel,
+            trainer_data::user_configuration_payload(rider, wheel_circumference),
+        ),
+        Duration::from_millis(100),
+    )
+    .map_err(|e| format!("writing user configuration: {}", e))?;
+
+    // the trainer reports whether it accepted the configuration in a command
+    // status page
+    node.write_message(
+        antrs::message::request_data_page(channel, trainer_data::PAGE_COMMAND_STATUS),
+        Duration::from_millis(100),
+    )
+    .map_err(|e| format!("requesting command status: {}", e))
+}
+
+#[tauri::command]
+async fn update_rider_profile(
+    state: State<'_, Arc<AppState>>,
+    rider: config::RiderProfile,
+) -> Result<(), String> {
+    let wheel_circumference = {
+        let mut config = state.config.write().unwrap();
+        config.rider = Some(rider);
+        config.save(CONFIG_FILE)?;
+        config.wheel_circumference
+    };
+
+    let channel = match *state.fe_state.read().unwrap() {
+        Some(ref fe) => fe.channel,
+        None => return Ok(()),
+    };
+
+    let node = state.node.lock().unwrap();
+    match *node {
+        Some(ref node) => send_user_configuration(node, channel, &rider, wheel_circumference),
+        None => Ok(()),
+    }
+}
+
+fn save_calibration(state: &AppState, result: calibration::CalibrationResult) {
+    let kind = if result.spin_down_success {
+        calibration::CalibrationKind::SpinDown
+    } else if result.zero_offset_success {
+        calibration::CalibrationKind::ZeroOffset
+    } else {
+        warn!("calibration failed: {:?}", result);
+        return;
+    };
+
+    let mut config = state.config.write().unwrap();
+    config.last_calibration = Some(config::Calibration {
+        kind,
+        date: days_ago(0),
+        zero_offset: result.zero_offset,
+        spin_down_time: result.spin_down_time,
+        temperature: result.temperature,
+    });
+
+    if let Err(e) = config.save(CONFIG_FILE) {
+        error!("saving calibration: {}", e);
+    }
+}
+
+#[tauri::command]
+async fn start_calibration(
+    state: State<'_, Arc<AppState>>,
+    kind: calibration::CalibrationKind,
+) -> Result<(), String> {
+    let channel = match *state.fe_state.read().unwrap() {
+        Some(ref fe) => fe.channel,
+        None => return Err("fitness equipment not connected".into()),
+    };
+
+    info!("requesting {:?} calibration", kind);
+
+    let node = state.node.lock().unwrap();
+    match *node {
+        Some(ref node) => node
+            .write_message(
+                antrs::message::acknowledged_data(channel, calibration::request_payload(kind)),
+                Duration::from_millis(100),
+            )
+            .map_err(|e| format!("writing calibration request: {}", e)),
+        None => Err("node not open".into()),
+    }
+}
+
+#[tauri::command]
+async fn get_calibration_status(
+    state: State<'_, Arc<AppState>>,
+) -> Result<calibration::CalibrationStatus, String> {
+    let last = state.config.read().unwrap().last_calibration;
+
+    Ok(calibration::status(last, days_ago(0)))
+}
+
 #[tauri::command]
 async fn load_workout(state: State<'_, Arc<AppState>>, data: String) -> Result<Workout, String> {
     let wko = workout::from_data_url(data, 0.)?;
@@ -200,6 +679,46 @@ async fn load_workout(state: State<'_, Arc<AppState>>, data: String) -> Result<W
     Ok(wko)
 }
 
+fn workout_warnings(state: &AppState, wko: &Workout) -> Vec<trainer_data::WorkoutWarning> {
+    let capabilities = state
+        .fe_state
+        .read()
+        .unwrap()
+        .as_ref()
+        .and_then(|fe| *fe.capabilities.read().unwrap());
+
+    trainer_data::check_workout(wko, capabilities)
+}
+
+#[tauri::command]
+async fn check_workout(
+    state: State<'_, Arc<AppState>>,
+) -> Result<Vec<trainer_data::WorkoutWarning>, String> {
+    if state.fe_state.read().unwrap().is_none() {
+        return Err("fitness equipment not connected".into());
+    }
+
+    let wko = state.workout.lock().unwrap();
+    match *wko {
+        Some(ref wko) => Ok(workout_warnings(&state, wko)),
+        None => Err("no workout loaded".into()),
+    }
+}
+
+#[tauri::command]
+async fn load_route(state: State<'_, Arc<AppState>>, data: String) -> Result<route::Route, String> {
+    let route = route::load_route(&workout::decode_data_url(&data, None)?)?;
+
+    info!(
+        "loaded route: {:?}, {:.0} m, {:.0} m ascent",
+        route.name, route.distance, route.ascent
+    );
+
+    *state.route.lock().unwrap() = Some(route.clone());
+
+    Ok(route)
+}
+
 #[tauri::command]
 async fn start_workout(state: State<'_, Arc<AppState>>, window: Window) -> Result<(), String> {
     let wko = {
@@ -210,6 +729,84 @@ async fn start_workout(state: State<'_, Arc<AppState>>, window: Window) -> Resul
         }
     };
 
+    let warnings = workout_warnings(&state, &wko);
+    for warning in warnings.iter() {
+        warn!("workout warning: {:?}", warning);
+    }
+    if let Err(e) = window.emit("workout_warnings", &warnings) {
+        error!("failed to emit workout_warnings: {}", e);
+    }
+
+    let title = wko.title.clone();
+
+    run_session(&state, &window, title, executor::Plan::Workout(wko))
+}
+
+#[tauri::command]
+async fn start_route(state: State<'_, Arc<AppState>>, window: Window) -> Result<(), String> {
+    let route = {
+        let route = state.route.lock().unwrap();
+        match route.clone() {
+            Some(route) => route,
+            None => return Err("no route loaded".into()),
+        }
+    };
+
+    let title = route.name.clone().unwrap_or_else(|| "Route".into());
+
+    run_session(&state, &window, title, executor::Plan::Route(route))
+}
+
+#[tauri::command]
+async fn start_free_ride(
+    state: State<'_, Arc<AppState>>,
+    window: Window,
+    target: executor::ManualTarget,
+) -> Result<(), String> {
+    run_session(
+        &state,
+        &window,
+        "Free ride".into(),
+        executor::Plan::FreeRide(target),
+    )
+}
+
+#[tauri::command]
+async fn set_free_ride_target(
+    state: State<'_, Arc<AppState>>,
+    target: executor::ManualTarget,
+) -> Result<(), String> {
+    info!("setting free ride target: {:?}", target);
+    state.control.lock().unwrap().manual_target = Some(target);
+
+    Ok(())
+}
+
+#[tauri::command]
+async fn set_heart_rate_target(
+    state: State<'_, Arc<AppState>>,
+    target: Option<heart_rate_control::HeartRateTarget>,
+) -> Result<(), String> {
+    info!("setting heart rate target: {:?}", target);
+    state.control.lock().unwrap().heart_rate_target = target;
+
+    Ok(())
+}
+
+#[tauri::command]
+async fn stop_session(state: State<'_, Arc<AppState>>) -> Result<(), String> {
+    info!("stopping session");
+    state.control.lock().unwrap().stop = true;
+
+    Ok(())
+}
+
+fn run_session(
+    state: &AppState,
+    window: &Window,
+    title: String,
+    plan: executor::Plan,
+) -> Result<(), String> {
     let fe_state = {
         let fe = state.fe_state.read().unwrap();
         match *fe {
@@ -218,17 +815,41 @@ async fn start_workout(state: State<'_, Arc<AppState>>, window: Window) -> Resul
         }
     };
 
+    *state.telemetry.lock().unwrap() = None;
+    {
+        // a heart rate target can be set before the session starts
+        let mut control = state.control.lock().unwrap();
+        *control = executor::Control {
+            heart_rate_target: control.heart_rate_target,
+            ..Default::default()
+        };
+    }
+    *state.route_position.lock().unwrap() = None;
+
+    let shared = executor::Shared {
+        status: Arc::clone(&state.workout_status),
+        telemetry: Arc::clone(&state.telemetry),
+        readings: Arc::clone(&state.readings),
+        control: Arc::clone(&state.control),
+        route_position: Arc::clone(&state.route_position),
+    };
+
     let wko_exec = executor::Executor::new(
         Arc::clone(&state.node),
-        Arc::clone(&state.workout_status),
-        wko,
+        shared,
+        plan,
         fe_state,
+        state.config.read().unwrap().clone(),
     );
 
     info!("starting workout");
 
     let wko_handle = std::thread::spawn(move || wko_exec.execute());
 
+    let mut telemetry_elapsed = None;
+    let mut route_distance = None;
+    let mut paused = false;
+
     while !wko_handle.is_finished() {
         let status = *state.workout_status.lock().unwrap();
 
@@ -236,18 +857,330 @@ async fn start_workout(state: State<'_, Arc<AppState>>, window: Window) -> Resul
             window
                 .emit("workout_status", status)
                 .map_err(|e| format!("emit workout_status: {}", e))?;
+
+            if status.paused != paused {
+                info!("auto-paused: {}", status.paused);
+                window
+                    .emit("auto_paused", status.paused)
+                    .map_err(|e| format!("emit auto_paused: {}", e))?;
+                paused = status.paused;
+            }
         } else {
             debug!("no workout status in UI update loop");
         }
 
+        let telemetry = *state.telemetry.lock().unwrap();
+
+        if let Some(telemetry) = telemetry {
+            if telemetry_elapsed != Some(telemetry.elapsed) {
+                window
+                    .emit("telemetry", telemetry)
+                    .map_err(|e| format!("emit telemetry: {}", e))?;
+                telemetry_elapsed = Some(telemetry.elapsed);
+            }
+        }
+
+        let position = *state.route_position.lock().unwrap();
+
+        if let Some(position) = position {
+            if route_distance != Some(position.distance) {
+                window
+                    .emit("route_position", position)
+                    .map_err(|e| format!("emit route_position: {}", e))?;
+                route_distance = Some(position.distance);
+            }
+        }
+
         std::thread::sleep(core::time::Duration::from_millis(200));
     }
 
     info!("workout complete, exiting UI update loop");
 
+    let recording = wko_handle
+        .join()
+        .map_err(|_| "workout executor panicked".to_string())?;
+
+    // a session stopped before any samples were recorded isn't worth keeping
+    if recording.samples.is_empty() {
+        info!("no samples recorded, not saving ride");
+        return Ok(());
+    }
+
+    let ride = history::Ride::new(title, recording, state.config.read().unwrap().ftp);
+
+    state.history.lock().unwrap().save(&ride)?;
+
     Ok(())
 }
 
+#[tauri::command]
+async fn set_grade_override(
+    state: State<'_, Arc<AppState>>,
+    grade: Option<f64>,
+) -> Result<(), String> {
+    info!("setting grade override: {:?}", grade);
+    state.control.lock().unwrap().grade_override = grade;
+
+    Ok(())
+}
+
+fn shift_gear(state: &AppState, up: bool) -> usize {
+    let gearing = state.config.read().unwrap().gearing.clone();
+    let mut control = state.control.lock().unwrap();
+
+    let gear = gearing.shift(control.gear.unwrap_or(gearing.initial_gear), up);
+    control.gear = Some(gear);
+
+    info!("shifted to gear {}", gear + 1);
+    gear + 1
+}
+
+#[tauri::command]
+async fn shift_up(state: State<'_, Arc<AppState>>) -> Result<usize, String> {
+    Ok(shift_gear(&state, true))
+}
+
+#[tauri::command]
+async fn shift_down(state: State<'_, Arc<AppState>>) -> Result<usize, String> {
+    Ok(shift_gear(&state, false))
+}
+
+fn days_ago(days: u32) -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+        .saturating_sub(days as u64 * training_load::SECONDS_PER_DAY)
+}
+
+#[tauri::command]
+async fn list_rides(
+    state: State<'_, Arc<AppState>>,
+    query: history::RideQuery,
+) -> Result<Vec<history::RideSummary>, String> {
+    Ok(state.history.lock().unwrap().list(&query))
+}
+
+#[tauri::command]
+async fn get_ride(state: State<'_, Arc<AppState>>, id: u64) -> Result<history::Ride, String> {
+    state.history.lock().unwrap().get(id)
+}
+
+#[tauri::command]
+async fn delete_ride(state: State<'_, Arc<AppState>>, id: u64) -> Result<(), String> {
+    state.history.lock().unwrap().delete(id)
+}
+
+#[tauri::command]
+async fn import_activities(
+    state: State<'_, Arc<AppState>>,
+    paths: Vec<String>,
+) -> Result<activity::ImportReport, String> {
+    let ftp = state.config.read().unwrap().ftp;
+    let mut history = state.history.lock().unwrap();
+
+    let report = activity::import_activities(&mut history, &paths, ftp);
+    info!(
+        "imported {} activities, {} duplicates, {} failures",
+        report.imported,
+        report.duplicates,
+        report.failures.len()
+    );
+
+    Ok(report)
+}
+
+#[tauri::command]
+async fn get_power_curve(
+    state: State<'_, Arc<AppState>>,
+    days: Option<u32>,
+    ride_id: Option<u64>,
+) -> Result<power_curve::PowerCurve, String> {
+    if let Some(ride_id) = ride_id {
+        return state.history.lock().unwrap().ride_power_curve(ride_id);
+    }
+
+    let since = days.map(days_ago);
+
+    Ok(state.history.lock().unwrap().power_curve(since))
+}
+
+#[tauri::command]
+async fn get_training_load(
+    state: State<'_, Arc<AppState>>,
+    days: u32,
+    planned: Vec<training_load::PlannedWorkout>,
+) -> Result<Vec<training_load::TrainingLoadDay>, String> {
+    let rides = state
+        .history
+        .lock()
+        .unwrap()
+        .list(&history::RideQuery::default())
+        .iter()
+        .map(|ride| (ride.start_time, ride.training_stress.unwrap_or(0.)))
+        .collect::<Vec<_>>();
+
+    Ok(training_load::training_load(
+        &rides,
+        days_ago(0),
+        days,
+        &planned,
+    ))
+}
+
+#[tauri::command]
+async fn get_critical_power(
+    state: State<'_, Arc<AppState>>,
+    days: Option<u32>,
+) -> Result<critical_power::CriticalPowerEstimate, String> {
+    let since = days.map(days_ago);
+
+    let curve = state.history.lock().unwrap().power_curve(since);
+    let configured_ftp = state.config.read().unwrap().ftp;
+
+    Ok(critical_power::estimate(&curve, configured_ftp))
+}
+
+#[tauri::command]
+async fn update_ftp(state: State<'_, Arc<AppState>>, ftp: u16) -> Result<(), String> {
+    let mut config = state.config.write().unwrap();
+
+    info!("updating ftp from {:?} to {}", config.ftp, ftp);
+    config.ftp = Some(ftp);
+    config.save(CONFIG_FILE)
+}
+
+#[tauri::command]
+async fn update_critical_power(
+    state: State<'_, Arc<AppState>>,
+    critical_power: u16,
+    w_prime: u32,
+) -> Result<(), String> {
+    let mut config = state.config.write().unwrap();
+
+    let w_prime_floor = config.critical_power.and_then(|cp| cp.w_prime_floor);
+    config.critical_power = Some(config::CriticalPower {
+        critical_power,
+        w_prime,
+        w_prime_floor,
+    });
+    config.save(CONFIG_FILE)
+}
+
+#[tauri::command]
+async fn estimate_training_stress(state: State<'_, Arc<AppState>>) -> Result<f64, String> {
+    let ftp = state
+        .config
+        .read()
+        .unwrap()
+        .ftp
+        .ok_or("ftp not configured")?;
+
+    let wko = state.workout.lock().unwrap();
+    match *wko {
+        Some(ref wko) => wko
+            .estimated_training_stress(ftp)
+            .ok_or_else(|| "workout has steps without a power target".into()),
+        None => Err("no workout loaded".into()),
+    }
+}
+
+const DEFAULT_SCAN_DURATION: u64 = 30;
+
+#[tauri::command]
+async fn start_scan(
+    state: State<'_, Arc<AppState>>,
+    window: Window,
+    kinds: Option<Vec<scan::DeviceKind>>,
+    duration: Option<u64>,
+) -> Result<(), String> {
+    {
+        let mut scanning = state.scanning.lock().unwrap();
+        if *scanning {
+            return Err("scan already in progress".into());
+        }
+        *scanning = true;
+    }
+
+    if let Some(ref node) = *state.node.lock().unwrap() {
+        // scans still work without it, just without RSSI
+        if let Err(e) = scan::enable_extended_data(node) {
+            warn!("{}", e);
+        }
+    }
+
+    let kinds = kinds.unwrap_or_else(|| scan::DeviceKind::ALL.to_vec());
+    let deadline =
+        std::time::Instant::now() + Duration::from_secs(duration.unwrap_or(DEFAULT_SCAN_DURATION));
+
+    info!("scanning for {:?}", kinds);
+
+    let handles = kinds
+        .into_iter()
+        .map(|kind| {
+            let node = Arc::clone(&state.node);
+            let window = window.clone();
+
+            std::thread::spawn(move || {
+                let result = scan::scan(node, kind, deadline, |device| {
+                    debug!("found device: {:?}", device);
+                    if let Err(e) = window.emit("device_found", device) {
+                        error!("failed to emit device_found: {}", e);
+                    }
+                });
+
+                if let Err(e) = result {
+                    warn!("scanning for {:?}: {}", kind, e);
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let state = Arc::clone(&state);
+
+    std::thread::spawn(move || {
+        for handle in handles {
+            let _ = handle.join();
+        }
+
+        *state.scanning.lock().unwrap() = false;
+
+        if let Err(e) = window.emit("scan_complete", true) {
+            error!("failed to emit scan_complete: {}", e);
+        }
+    });
+
+    Ok(())
+}
+
+#[tauri::command]
+async fn save_pairing(
+    state: State<'_, Arc<AppState>>,
+    kind: scan::DeviceKind,
+    device_id: u16,
+    transmission_type: u8,
+) -> Result<(), String> {
+    let pairing = config::DevicePairing {
+        device_id,
+        transmission_type,
+    };
+
+    let mut config = state.config.write().unwrap();
+
+    let pairings = match kind {
+        scan::DeviceKind::HeartRateMonitor => &mut config.devices.heart_rate_monitor,
+        scan::DeviceKind::FitnessEquipment => &mut config.devices.fitness_equipment,
+        scan::DeviceKind::BicyclePower => &mut config.devices.power_meter,
+        scan::DeviceKind::BikeSpeedCadence => &mut config.devices.speed_cadence_sensor,
+        scan::DeviceKind::BikeSpeed => &mut config.devices.speed_sensor,
+        scan::DeviceKind::BikeCadence => &mut config.devices.cadence_sensor,
+    };
+    config::add_pairing(pairings, pairing);
+
+    info!("saving {:?} pairing: {:?}", kind, pairing);
+    config.save(CONFIG_FILE)
+}
+
 fn main() {
     env_logger::builder()
         .format_timestamp_millis()
@@ -256,17 +1189,27 @@ fn main() {
         .target(env_logger::Target::Stdout)
         .init();
 
-    let app_config = std::fs::read_to_string("appconfig.toml").expect("read config file");
-    let app_config: config::AppConfig = toml::from_str(&app_config).expect("parse config file");
+    let app_config = config::AppConfig::load(CONFIG_FILE).expect("load config file");
     trace!("loaded application config: {:?}", app_config);
 
+    let ride_history = history::History::open(&app_config.history_dir).expect("open ride history");
+
+    let readings = recorder::Readings::new(app_config.sources.clone());
+
     let state = Arc::new(AppState {
-        config: app_config,
+        config: app_config.into(),
         node: Arc::new(Mutex::new(None)),
         fe_state: None.into(),
         hrm_channel: None.into(),
         workout: None.into(),
+        route: None.into(),
         workout_status: Arc::new(Mutex::new(None)),
+        telemetry: Arc::new(Mutex::new(None)),
+        readings: Arc::new(Mutex::new(readings)),
+        control: Arc::new(Mutex::new(executor::Control::default())),
+        route_position: Arc::new(Mutex::new(None)),
+        history: Mutex::new(ride_history),
+        scanning: Mutex::new(false),
     });
 
     let mut sleep_lock = nosleep::NoSleep::new().unwrap();
@@ -280,8 +1223,35 @@ fn main() {
             open_node,
             open_fitness_equipment,
             open_hrm,
+            open_power_meter,
+            open_speed_cadence,
             load_workout,
-            start_workout
+            check_workout,
+            start_workout,
+            load_route,
+            start_route,
+            start_free_ride,
+            set_free_ride_target,
+            set_heart_rate_target,
+            stop_session,
+            set_grade_override,
+            shift_up,
+            shift_down,
+            list_rides,
+            get_ride,
+            delete_ride,
+            import_activities,
+            get_power_curve,
+            get_training_load,
+            get_critical_power,
+            update_ftp,
+            update_critical_power,
+            update_rider_profile,
+            estimate_training_stress,
+            start_scan,
+            save_pairing,
+            start_calibration,
+            get_calibration_status
         ])
         .build(tauri::generate_context!())
         .expect("error while building tauri application");
@@ -0,0 +1,220 @@
+use std::collections::VecDeque;
+
+const NORMALIZED_POWER_WINDOW: usize = 30;
+
+pub fn average_power(power: &[u16]) -> f64 {
+    if power.is_empty() {
+        0.
+    } else {
+        power.iter().map(|&p| p as f64).sum::<f64>() / power.len() as f64
+    }
+}
+
+pub fn normalized_power(power: &[u16]) -> f64 {
+    segmented_normalized_power(&[power])
+}
+
+// Rolling averages are only taken within each segment, so data on either side
+// of a gap in the recording is never averaged together.
+pub fn segmented_normalized_power<S: AsRef<[u16]>>(segments: &[S]) -> f64 {
+    let rolling = segments
+        .iter()
+        .flat_map(|segment| segment.as_ref().windows(NORMALIZED_POWER_WINDOW))
+        .map(|window| average_power(window).powi(4))
+        .collect::<Vec<_>>();
+
+    if rolling.is_empty() {
+        let power = segments
+            .iter()
+            .flat_map(|segment| segment.as_ref().iter().copied())
+            .collect::<Vec<_>>();
+        return average_power(&power);
+    }
+
+    (rolling.iter().sum::<f64>() / rolling.len() as f64).powf(0.25)
+}
+
+pub fn intensity_factor(normalized_power: f64, ftp: u16) -> f64 {
+    normalized_power / ftp as f64
+}
+
+pub fn training_stress(duration: u32, normalized_power: f64, ftp: u16) -> f64 {
+    let intensity = intensity_factor(normalized_power, ftp);
+
+    (duration as f64 * normalized_power * intensity) / (ftp as f64 * 3600.) * 100.
+}
+
+// Accumulates 1 Hz samples during a ride so ride metrics can be reported live
+// without reprocessing the whole ride every second.
+pub struct LiveMetrics {
+    ftp: Option<u16>,
+    recent_power: VecDeque<u16>,
+    samples: u32,
+    power_sum: u64,
+    rolling_fourth_power_sum: f64,
+    rolling_count: u32,
+    cadence_sum: u64,
+    cadence_count: u32,
+    heart_rate_sum: u64,
+    heart_rate_count: u32,
+}
+
+impl LiveMetrics {
+    pub fn new(ftp: Option<u16>) -> LiveMetrics {
+        LiveMetrics {
+            ftp,
+            recent_power: VecDeque::with_capacity(NORMALIZED_POWER_WINDOW),
+            samples: 0,
+            power_sum: 0,
+            rolling_fourth_power_sum: 0.,
+            rolling_count: 0,
+            cadence_sum: 0,
+            cadence_count: 0,
+            heart_rate_sum: 0,
+            heart_rate_count: 0,
+        }
+    }
+
+    pub fn update(&mut self, power: Option<u16>, cadence: Option<u8>, heart_rate: Option<u8>) {
+        let power = power.unwrap_or(0);
+
+        if self.recent_power.len() == NORMALIZED_POWER_WINDOW {
+            self.recent_power.pop_front();
+        }
+        self.recent_power.push_back(power);
+
+        self.samples += 1;
+        self.power_sum += power as u64;
+
+        if self.recent_power.len() == NORMALIZED_POWER_WINDOW {
+            self.rolling_fourth_power_sum += self.rolling_power(NORMALIZED_POWER_WINDOW).powi(4);
+            self.rolling_count += 1;
+        }
+
+        if let Some(cadence) = cadence.filter(|&c| c > 0) {
+            self.cadence_sum += cadence as u64;
+            self.cadence_count += 1;
+        }
+
+        if let Some(heart_rate) = heart_rate {
+            self.heart_rate_sum += heart_rate as u64;
+            self.heart_rate_count += 1;
+        }
+    }
+
+    pub fn rolling_power(&self, seconds: usize) -> f64 {
+        let count = seconds.min(self.recent_power.len());
+        if count == 0 {
+            return 0.;
+        }
+
+        self.recent_power
+            .iter()
+            .rev()
+            .take(count)
+            .map(|&p| p as f64)
+            .sum::<f64>()
+            / count as f64
+    }
+
+    pub fn average_power(&self) -> f64 {
+        if self.samples == 0 {
+            0.
+        } else {
+            self.power_sum as f64 / self.samples as f64
+        }
+    }
+
+    pub fn normalized_power(&self) -> f64 {
+        if self.rolling_count == 0 {
+            self.average_power()
+        } else {
+            (self.rolling_fourth_power_sum / self.rolling_count as f64).powf(0.25)
+        }
+    }
+
+    pub fn intensity_factor(&self) -> Option<f64> {
+        self.ftp
+            .map(|ftp| intensity_factor(self.normalized_power(), ftp))
+    }
+
+    pub fn training_stress(&self) -> Option<f64> {
+        self.ftp
+            .map(|ftp| training_stress(self.samples, self.normalized_power(), ftp))
+    }
+
+    pub fn work(&self) -> f64 {
+        self.power_sum as f64 / 1000.
+    }
+
+    pub fn average_cadence(&self) -> Option<f64> {
+        (self.cadence_count > 0).then(|| self.cadence_sum as f64 / self.cadence_count as f64)
+    }
+
+    pub fn average_heart_rate(&self) -> Option<f64> {
+        (self.heart_rate_count > 0)
+            .then(|| self.heart_rate_sum as f64 / self.heart_rate_count as f64)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::metrics::{self, LiveMetrics};
+
+    #[test]
+    fn it_normalizes_constant_power_to_itself() {
+        let power = vec![200; 600];
+
+        assert!((metrics::normalized_power(&power) - 200.).abs() < 1e-9);
+    }
+
+    #[test]
+    fn it_weights_variable_power_above_average() {
+        let power = (0..1200)
+            .map(|i| if (i / 60) % 2 == 0 { 300 } else { 100 })
+            .collect::<Vec<u16>>();
+
+        assert!(metrics::normalized_power(&power) > metrics::average_power(&power));
+    }
+
+    #[test]
+    fn it_does_not_average_across_segments() {
+        let segments = [vec![300; 60], vec![100; 60]];
+        let joined = segments.concat();
+
+        let expected = ((300f64.powi(4) * 31. + 100f64.powi(4) * 31.) / 62.).powf(0.25);
+
+        assert!((metrics::segmented_normalized_power(&segments) - expected).abs() < 1e-9);
+        assert!(
+            metrics::segmented_normalized_power(&segments) > metrics::normalized_power(&joined)
+        );
+    }
+
+    #[test]
+    fn it_matches_batch_metrics_when_accumulating_live() {
+        let power = (0..600)
+            .map(|i| 150 + (i * 37 % 200) as u16)
+            .collect::<Vec<_>>();
+
+        let mut live = LiveMetrics::new(Some(250));
+        for &p in power.iter() {
+            live.update(Some(p), Some(90), None);
+        }
+
+        assert!((live.normalized_power() - metrics::normalized_power(&power)).abs() < 1e-6);
+        assert!((live.average_power() - metrics::average_power(&power)).abs() < 1e-9);
+        assert_eq!(
+            live.rolling_power(3),
+            (power[597..].iter().sum::<u16>() as f64) / 3.
+        );
+        assert_eq!(live.average_cadence(), Some(90.));
+        assert_eq!(live.average_heart_rate(), None);
+    }
+
+    #[test]
+    fn it_scores_one_hour_at_ftp_as_100_tss() {
+        let tss = metrics::training_stress(3600, 250., 250);
+
+        assert!((tss - 100.).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,105 @@
+use crate::config::{RiderProfile, Simulation};
+
+// metres per second squared
+const GRAVITY: f64 = 9.81;
+
+// kilograms, used until a rider profile is configured
+const DEFAULT_MASS: f64 = 85.;
+
+// seconds, longest interval integrated in one go
+const MAX_STEP: f64 = 0.1;
+
+pub fn mass(rider: Option<RiderProfile>) -> f64 {
+    rider.map_or(DEFAULT_MASS, |r| r.rider_weight + r.bike_weight)
+}
+
+// Integrates rider power into the speed and distance of a virtual bike on a
+// road of the given grade, so descents carry momentum and climbs slow it down.
+#[derive(Clone, Copy, Debug)]
+pub struct VirtualBike {
+    mass: f64,
+    simulation: Simulation,
+    speed: f64,
+    distance: f64,
+}
+
+impl VirtualBike {
+    pub fn new(mass: f64, simulation: Simulation) -> VirtualBike {
+        VirtualBike {
+            mass,
+            simulation,
+            speed: 0.,
+            distance: 0.,
+        }
+    }
+
+    // Force resisting the bike at a given speed, in newtons. Negative when
+    // gravity pulls it down a descent harder than rolling resistance and drag.
+    fn resistance(&self, speed: f64, grade: f64) -> f64 {
+        let angle = (grade / 100.).atan();
+
+        let gravity = self.mass * GRAVITY * angle.sin();
+        let rolling = self.mass * GRAVITY * angle.cos() * self.simulation.rolling_resistance;
+        let drag = 0.5
+            * self.simulation.wind_resistance_coefficient()
+            * self.simulation.drafting_factor
+            * speed
+            * speed;
+
+        gravity + rolling + drag
+    }
+
+    // Advances the bike by `dt` seconds at the given power and grade (percent).
+    pub fn update(&mut self, power: u16, grade: f64, dt: f64) {
+        let steps = (dt / MAX_STEP).ceil().max(1.);
+        let dt = dt / steps;
+
+        for _ in 0..steps as u32 {
+            // power goes in as energy so it can move the bike from a standstill,
+            // resistance as a force so gravity can too
+            let speed = (self.speed * self.speed + 2. * power as f64 * dt / self.mass).sqrt()
+                - self.resistance(self.speed, grade) / self.mass * dt;
+            let speed = speed.max(0.);
+
+            self.distance += (self.speed + speed) / 2. * dt;
+            self.speed = speed;
+        }
+    }
+
+    // metres per second
+    pub fn speed(&self) -> f64 {
+        self.speed
+    }
+
+    // metres
+    pub fn distance(&self) -> f64 {
+        self.distance
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::config::Simulation;
+    use crate::physics::VirtualBike;
+
+    #[test]
+    fn it_settles_at_steady_state_speed() {
+        let mut flat = VirtualBike::new(80., Simulation::default());
+        let mut climb = VirtualBike::new(80., Simulation::default());
+
+        for _ in 0..600 {
+            flat.update(200, 0., 1.);
+            climb.update(200, 8., 1.);
+        }
+
+        // 200 W balances rolling resistance and drag at ~8.8 m/s on the flat,
+        // and mostly gravity at ~2.9 m/s up an 8% climb
+        assert!((flat.speed() - 8.8).abs() < 0.1, "speed: {}", flat.speed());
+        assert!(
+            (climb.speed() - 2.9).abs() < 0.1,
+            "speed: {}",
+            climb.speed()
+        );
+        assert!(flat.distance() > 5000.);
+    }
+}
@@ -0,0 +1,191 @@
+use std::collections::BTreeMap;
+
+use ts_rs::TS;
+
+pub const DURATIONS: [u32; 26] = [
+    1, 2, 3, 5, 10, 15, 20, 30, 45, 60, 90, 120, 180, 240, 300, 360, 480, 600, 720, 900, 1200,
+    1500, 1800, 2400, 3000, 3600,
+];
+
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize, TS)]
+#[ts(export, export_to = "../src/types/PowerCurvePoint.ts")]
+pub struct PowerCurvePoint {
+    pub duration: u32,
+    pub power: u16,
+    #[ts(type = "number")]
+    pub ride_id: u64,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, serde::Deserialize, serde::Serialize, TS)]
+#[ts(export, export_to = "../src/types/PowerCurve.ts")]
+pub struct PowerCurve {
+    pub points: Vec<PowerCurvePoint>,
+}
+
+impl PowerCurve {
+    pub fn merge(&mut self, ride_id: u64, mean_max: &[u16]) {
+        for (i, &power) in mean_max.iter().enumerate() {
+            match self.points.get_mut(i) {
+                Some(point) => {
+                    if power > point.power {
+                        point.power = power;
+                        point.ride_id = ride_id;
+                    }
+                }
+                None => self.points.push(PowerCurvePoint {
+                    duration: DURATIONS[i],
+                    power,
+                    ride_id,
+                }),
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+pub struct RideCurve {
+    pub start_time: u64,
+    pub mean_max: Vec<u16>,
+}
+
+// Mean-max values for every recorded ride along with the all-time best curve,
+// which is kept up to date as rides are added so it doesn't need recomputing.
+#[derive(Clone, Debug, Default, serde::Deserialize, serde::Serialize)]
+pub struct PowerCurves {
+    pub rides: BTreeMap<u64, RideCurve>,
+    pub best: PowerCurve,
+}
+
+impl PowerCurves {
+    pub fn insert<S: AsRef<[u16]>>(&mut self, ride_id: u64, start_time: u64, segments: &[S]) {
+        let mean_max = segmented_mean_max(segments);
+
+        self.best.merge(ride_id, &mean_max);
+        self.rides.insert(
+            ride_id,
+            RideCurve {
+                start_time,
+                mean_max,
+            },
+        );
+    }
+
+    pub fn remove(&mut self, ride_id: u64) {
+        if self.rides.remove(&ride_id).is_some() {
+            self.best = self.best_since(None);
+        }
+    }
+
+    pub fn ride(&self, ride_id: u64) -> Option<PowerCurve> {
+        self.rides.get(&ride_id).map(|ride| {
+            let mut curve = PowerCurve::default();
+            curve.merge(ride_id, &ride.mean_max);
+            curve
+        })
+    }
+
+    pub fn best_since(&self, since: Option<u64>) -> PowerCurve {
+        let mut curve = PowerCurve::default();
+
+        for (&ride_id, ride) in self.rides.iter() {
+            if since.is_none_or(|since| ride.start_time >= since) {
+                curve.merge(ride_id, &ride.mean_max);
+            }
+        }
+
+        curve
+    }
+}
+
+// The best of each segment's curve, so no window spans a gap in the recording.
+pub fn segmented_mean_max<S: AsRef<[u16]>>(segments: &[S]) -> Vec<u16> {
+    let mut best: Vec<u16> = vec![];
+
+    for curve in segments.iter().map(|segment| mean_max(segment.as_ref())) {
+        for (i, value) in curve.into_iter().enumerate() {
+            match best.get_mut(i) {
+                Some(best) => *best = (*best).max(value),
+                None => best.push(value),
+            }
+        }
+    }
+
+    best
+}
+
+pub fn mean_max(power: &[u16]) -> Vec<u16> {
+    let mut cumulative = Vec::with_capacity(power.len() + 1);
+    cumulative.push(0u64);
+    for &p in power {
+        cumulative.push(cumulative.last().unwrap() + p as u64);
+    }
+
+    DURATIONS
+        .iter()
+        .map(|&d| d as usize)
+        .take_while(|&d| d <= power.len())
+        .map(|d| {
+            let best = (d..cumulative.len())
+                .map(|end| cumulative[end] - cumulative[end - d])
+                .max()
+                .unwrap_or(0);
+
+            (best as f64 / d as f64).round() as u16
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::power_curve::{self, PowerCurves};
+
+    #[test]
+    fn it_computes_mean_max_for_available_durations() {
+        let mut power = vec![100; 120];
+        power[10] = 500;
+        for p in power.iter_mut().skip(60).take(30) {
+            *p = 300;
+        }
+
+        let mean_max = power_curve::mean_max(&power);
+
+        assert_eq!(mean_max.len(), 12);
+        assert_eq!(mean_max[0], 500);
+        assert_eq!(mean_max[7], 300);
+        assert_eq!(mean_max[9], 200);
+        assert_eq!(mean_max[11], 153);
+    }
+
+    #[test]
+    fn it_keeps_best_curve_across_rides() {
+        let mut curves = PowerCurves::default();
+
+        curves.insert(1, 1000, &[[300; 10]]);
+        curves.insert(2, 2000, &[[200; 20]]);
+
+        let best = curves
+            .best
+            .points
+            .iter()
+            .map(|p| (p.power, p.ride_id))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            best,
+            vec![
+                (300, 1),
+                (300, 1),
+                (300, 1),
+                (300, 1),
+                (300, 1),
+                (200, 2),
+                (200, 2)
+            ]
+        );
+
+        let recent = curves.best_since(Some(1500));
+        assert!(recent.points.iter().all(|p| p.ride_id == 2));
+
+        curves.remove(1);
+        assert!(curves.best.points.iter().all(|p| p.power == 200));
+    }
+}
@@ -0,0 +1,123 @@
+use crate::config;
+
+// seconds to wait after the set point changes before correcting, so the
+// trainer's own ramp to the new target isn't mistaken for an offset
+const SETTLE_TIME: u32 = 5;
+
+// watts; set point changes smaller than this, such as ramps or heart rate
+// control moving the target, keep the smoothed power rather than settling again
+const SETTLE_STEP: u16 = 20;
+
+// fraction of the remaining error corrected each second
+const GAIN: f64 = 0.1;
+
+// Steers the trainer target so the power meter, rather than the trainer, reads
+// the set point. Updated once per second with the power meter's reading.
+pub struct PowerMatch {
+    max_offset: f64,
+    smoothing: f64,
+    smoothed: Option<f64>,
+    offset: f64,
+    set_point: Option<u16>,
+    settling: u32,
+}
+
+impl PowerMatch {
+    pub fn new(config: config::PowerMatch) -> PowerMatch {
+        PowerMatch {
+            max_offset: config.max_offset as f64,
+            smoothing: config.smoothing.max(1) as f64,
+            smoothed: None,
+            offset: 0.,
+            set_point: None,
+            settling: 0,
+        }
+    }
+
+    pub fn update(&mut self, set_point: u16, meter_power: Option<u16>) {
+        if self
+            .set_point
+            .is_none_or(|last| last.abs_diff(set_point) >= SETTLE_STEP)
+        {
+            self.settling = SETTLE_TIME;
+            self.smoothed = None;
+        }
+        self.set_point = Some(set_point);
+
+        // hold the current offset while coasting or without power meter data
+        let meter_power = match meter_power.filter(|&p| p > 0) {
+            Some(power) => power as f64,
+            None => return,
+        };
+
+        let smoothed = match self.smoothed {
+            Some(smoothed) => smoothed + (meter_power - smoothed) / self.smoothing,
+            None => meter_power,
+        };
+        self.smoothed = Some(smoothed);
+
+        if self.settling > 0 {
+            self.settling -= 1;
+            return;
+        }
+
+        let error = set_point as f64 - smoothed;
+        self.offset = (self.offset + error * GAIN).clamp(-self.max_offset, self.max_offset);
+    }
+
+    pub fn offset(&self) -> i16 {
+        self.offset.round() as i16
+    }
+
+    pub fn target(&self, power: u16) -> u16 {
+        (power as i32 + self.offset() as i32).max(0) as u16
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::config;
+    use crate::power_match::PowerMatch;
+
+    #[test]
+    fn it_converges_on_the_power_meter_within_bounds() {
+        let mut power_match = PowerMatch::new(config::PowerMatch {
+            max_offset: 30,
+            smoothing: 5,
+        });
+
+        // the trainer reads 8% high, so the pedals see less than the target
+        for _ in 0..300 {
+            let sent = power_match.target(250);
+            power_match.update(250, Some((sent as f64 / 1.08).round() as u16));
+        }
+        assert!((power_match.target(250) as i32 - 270).abs() <= 1);
+
+        // the correction never exceeds the configured bound
+        for _ in 0..300 {
+            power_match.update(250, Some(150));
+        }
+        assert_eq!(power_match.offset(), 30);
+    }
+
+    #[test]
+    fn it_keeps_matching_while_the_set_point_ramps() {
+        let mut power_match = PowerMatch::new(config::PowerMatch {
+            max_offset: 30,
+            smoothing: 5,
+        });
+
+        // a watt a second, as heart rate control moves the target, with a
+        // step back down every 40 s
+        for second in 0..300 {
+            let set_point = 200 + (second % 40) as u16;
+            let sent = power_match.target(set_point);
+            power_match.update(set_point, Some((sent as f64 / 1.08).round() as u16));
+        }
+        assert!(
+            power_match.offset() >= 10,
+            "offset: {}",
+            power_match.offset()
+        );
+    }
+}
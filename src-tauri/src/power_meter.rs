@@ -0,0 +1,170 @@
+use std::f64::consts::PI;
+
+use ts_rs::TS;
+
+const PAGE_STANDARD_POWER: u8 = 0x10;
+const PAGE_WHEEL_TORQUE: u8 = 0x11;
+const PAGE_CRANK_TORQUE: u8 = 0x12;
+
+const PEDAL_POWER_NOT_USED: u8 = 0xff;
+const PEDAL_POWER_RIGHT: u8 = 0x80;
+const CADENCE_INVALID: u8 = 0xff;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, serde::Serialize, TS)]
+#[ts(export, export_to = "../src/types/PowerMeterUpdate.ts")]
+pub struct PowerMeterUpdate {
+    pub power: Option<u16>,
+    pub cadence: Option<u8>,
+    // percentage of power contributed by the right pedal
+    pub right_balance: Option<u8>,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct TorquePage {
+    event_count: u8,
+    ticks: u8,
+    period: u16,
+    torque: u16,
+}
+
+impl TorquePage {
+    fn parse(payload: &[u8; 8]) -> TorquePage {
+        TorquePage {
+            event_count: payload[1],
+            ticks: payload[2],
+            period: u16::from_le_bytes([payload[4], payload[5]]),
+            torque: u16::from_le_bytes([payload[6], payload[7]]),
+        }
+    }
+}
+
+// Decodes bicycle power profile pages. Torque pages carry accumulated values,
+// so power and cadence are derived from the change since the previous page.
+#[derive(Debug, Default)]
+pub struct PowerMeterDecoder {
+    last_wheel_torque: Option<TorquePage>,
+    last_crank_torque: Option<TorquePage>,
+    current: PowerMeterUpdate,
+}
+
+impl PowerMeterDecoder {
+    pub fn decode(&mut self, payload: &[u8; 8]) -> Option<PowerMeterUpdate> {
+        match payload[0] {
+            PAGE_STANDARD_POWER => {
+                self.current.power = Some(u16::from_le_bytes([payload[6], payload[7]]));
+                self.current.right_balance = match payload[2] {
+                    PEDAL_POWER_NOT_USED => None,
+                    pedal_power if pedal_power & PEDAL_POWER_RIGHT != 0 => {
+                        Some(pedal_power & !PEDAL_POWER_RIGHT)
+                    }
+                    _ => None,
+                };
+                if let Some(cadence) = instantaneous_cadence(payload) {
+                    self.current.cadence = Some(cadence);
+                }
+            }
+            PAGE_WHEEL_TORQUE => {
+                let page = TorquePage::parse(payload);
+                if let Some(power) = self
+                    .last_wheel_torque
+                    .and_then(|last| torque_power(last, page))
+                {
+                    self.current.power = Some(power);
+                }
+                self.last_wheel_torque = Some(page);
+
+                if let Some(cadence) = instantaneous_cadence(payload) {
+                    self.current.cadence = Some(cadence);
+                }
+            }
+            PAGE_CRANK_TORQUE => {
+                let page = TorquePage::parse(payload);
+                if let Some(last) = self.last_crank_torque {
+                    if let Some(power) = torque_power(last, page) {
+                        self.current.power = Some(power);
+                    }
+                    // crank ticks give the average cadence since the last event
+                    if let Some(cadence) = crank_cadence(last, page) {
+                        self.current.cadence = Some(cadence);
+                    }
+                }
+                self.last_crank_torque = Some(page);
+
+                if let Some(cadence) = instantaneous_cadence(payload) {
+                    self.current.cadence = Some(cadence);
+                }
+            }
+            _ => return None,
+        }
+
+        Some(self.current)
+    }
+}
+
+fn instantaneous_cadence(payload: &[u8; 8]) -> Option<u8> {
+    (payload[3] != CADENCE_INVALID).then_some(payload[3])
+}
+
+// Average power between two torque pages: 2π · torque / period, with torque in
+// 1/32 Nm and period in 1/2048 s.
+fn torque_power(last: TorquePage, page: TorquePage) -> Option<u16> {
+    if page.event_count == last.event_count {
+        return None;
+    }
+
+    let period = page.period.wrapping_sub(last.period);
+    if period == 0 {
+        return Some(0);
+    }
+
+    let torque = page.torque.wrapping_sub(last.torque);
+
+    Some((2. * PI * (torque as f64 / 32.) / (period as f64 / 2048.)).round() as u16)
+}
+
+fn crank_cadence(last: TorquePage, page: TorquePage) -> Option<u8> {
+    let period = page.period.wrapping_sub(last.period);
+    if page.event_count == last.event_count || period == 0 {
+        return None;
+    }
+
+    let ticks = page.ticks.wrapping_sub(last.ticks);
+
+    Some(
+        (60. * ticks as f64 / (period as f64 / 2048.))
+            .round()
+            .min(254.) as u8,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use crate::power_meter::PowerMeterDecoder;
+
+    #[test]
+    fn it_decodes_standard_power_page() {
+        let mut decoder = PowerMeterDecoder::default();
+
+        let update = decoder
+            .decode(&[0x10, 0x01, 0xb2, 0x5a, 0x00, 0x01, 0xfa, 0x00])
+            .expect("page decodes");
+
+        assert_eq!(update.power, Some(250));
+        assert_eq!(update.cadence, Some(90));
+        assert_eq!(update.right_balance, Some(50));
+    }
+
+    #[test]
+    fn it_derives_power_and_cadence_from_crank_torque() {
+        let mut decoder = PowerMeterDecoder::default();
+
+        // one revolution per 2/3 s (90 rpm) at 25 Nm: 2π · 25 · 1.5 ≈ 236 W
+        decoder.decode(&[0x12, 0x01, 0x01, 0xff, 0x00, 0x00, 0x00, 0x00]);
+        let update = decoder
+            .decode(&[0x12, 0x02, 0x02, 0xff, 0x55, 0x05, 0x20, 0x03])
+            .expect("page decodes");
+
+        assert_eq!(update.power, Some(236));
+        assert_eq!(update.cadence, Some(90));
+    }
+}
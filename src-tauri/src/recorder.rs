@@ -0,0 +1,205 @@
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::config::{Source, SourcePriority};
+use crate::history::Sample;
+
+const READING_TIMEOUT: Duration = Duration::from_secs(3);
+
+#[derive(Clone, Copy, Debug)]
+struct Reading<T> {
+    value: T,
+    received_at: Instant,
+}
+
+impl<T: Copy> Reading<T> {
+    fn new(value: T) -> Reading<T> {
+        Reading {
+            value,
+            received_at: Instant::now(),
+        }
+    }
+
+    fn fresh(reading: Option<Reading<T>>) -> Option<T> {
+        reading
+            .filter(|r| r.received_at.elapsed() < READING_TIMEOUT)
+            .map(|r| r.value)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct SourceReadings {
+    power: Option<Reading<u16>>,
+    cadence: Option<Reading<u8>>,
+    speed: Option<Reading<f64>>,
+    distance: Option<Reading<f64>>,
+}
+
+// Latest values received from the connected devices, shared between the device
+// channel threads and the workout executor. Values reported by more than one
+// device are taken from the first fresh source in the configured priority.
+#[derive(Clone, Debug, Default)]
+pub struct Readings {
+    priority: SourcePriority,
+    sources: [SourceReadings; 4],
+    heart_rate: Option<Reading<u8>>,
+    calibration_required: bool,
+}
+
+impl Readings {
+    pub fn new(priority: SourcePriority) -> Readings {
+        Readings {
+            priority,
+            ..Default::default()
+        }
+    }
+
+    fn source(&mut self, source: Source) -> &mut SourceReadings {
+        &mut self.sources[source as usize]
+    }
+
+    pub fn set_power(&mut self, source: Source, power: Option<u16>) {
+        self.source(source).power = power.map(Reading::new);
+    }
+
+    pub fn set_cadence(&mut self, source: Source, cadence: Option<u8>) {
+        self.source(source).cadence = cadence.map(Reading::new);
+    }
+
+    pub fn set_speed(&mut self, source: Source, speed: Option<f64>) {
+        self.source(source).speed = speed.map(Reading::new);
+    }
+
+    pub fn set_heart_rate(&mut self, heart_rate: u8) {
+        self.heart_rate = Some(Reading::new(heart_rate));
+    }
+
+    pub fn set_distance(&mut self, source: Source, distance: Option<f64>) {
+        self.source(source).distance = distance.map(Reading::new);
+    }
+
+    pub fn set_calibration_required(&mut self, calibration_required: bool) {
+        self.calibration_required = calibration_required;
+    }
+
+    pub fn power(&self) -> Option<u16> {
+        self.priority
+            .power
+            .iter()
+            .find_map(|&source| self.power_from(source))
+    }
+
+    pub fn power_from(&self, source: Source) -> Option<u16> {
+        Reading::fresh(self.sources[source as usize].power)
+    }
+
+    pub fn cadence(&self) -> Option<u8> {
+        self.priority
+            .cadence
+            .iter()
+            .find_map(|&source| Reading::fresh(self.sources[source as usize].cadence))
+    }
+
+    pub fn speed(&self) -> Option<f64> {
+        self.priority
+            .speed
+            .iter()
+            .find_map(|&source| Reading::fresh(self.sources[source as usize].speed))
+    }
+
+    pub fn speed_from(&self, source: Source) -> Option<f64> {
+        Reading::fresh(self.sources[source as usize].speed)
+    }
+
+    pub fn heart_rate(&self) -> Option<u8> {
+        Reading::fresh(self.heart_rate)
+    }
+
+    // distance comes from the same sources as speed
+    pub fn distance(&self) -> Option<(Source, f64)> {
+        self.priority.speed.iter().find_map(|&source| {
+            Reading::fresh(self.sources[source as usize].distance).map(|d| (source, d))
+        })
+    }
+
+    pub fn calibration_required(&self) -> bool {
+        self.calibration_required
+    }
+}
+
+pub struct Recording {
+    pub start_time: SystemTime,
+    pub samples: Vec<Sample>,
+    pub calibration_required: bool,
+    // devices report distance since they were connected rather than since
+    // the ride started, each from a different point
+    distance_offsets: [Option<f64>; 4],
+}
+
+impl Recording {
+    pub fn start() -> Recording {
+        Recording {
+            start_time: SystemTime::now(),
+            samples: Vec::new(),
+            calibration_required: false,
+            distance_offsets: [None; 4],
+        }
+    }
+
+    pub fn record(&mut self, elapsed: u32, readings: &Readings) {
+        let distance = readings.distance().map(|(source, distance)| {
+            // a source first used mid-ride carries on from the distance so far
+            let recorded = self
+                .samples
+                .iter()
+                .rev()
+                .find_map(|s| s.distance)
+                .unwrap_or(0.);
+            let offset = *self.distance_offsets[source as usize].get_or_insert(distance - recorded);
+            distance - offset
+        });
+
+        self.samples.push(Sample {
+            elapsed,
+            power: readings.power(),
+            cadence: readings.cadence(),
+            heart_rate: readings.heart_rate(),
+            speed: readings.speed(),
+            distance,
+        });
+
+        self.calibration_required |= readings.calibration_required();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::config::{Source, SourcePriority};
+    use crate::recorder::{Readings, Recording};
+
+    #[test]
+    fn it_offsets_distance_per_source() {
+        let mut readings = Readings::new(SourcePriority::default());
+        let mut recording = Recording::start();
+
+        let mut record = |readings: &Readings| {
+            recording.record(0, readings);
+            recording.samples.last().unwrap().distance
+        };
+
+        readings.set_distance(Source::Trainer, Some(1000.));
+        assert_eq!(record(&readings), Some(0.));
+        readings.set_distance(Source::Trainer, Some(1010.));
+        assert_eq!(record(&readings), Some(10.));
+
+        // a speed sensor takes over without the distance jumping
+        readings.set_distance(Source::SpeedCadence, Some(50.));
+        assert_eq!(record(&readings), Some(10.));
+        readings.set_distance(Source::SpeedCadence, Some(65.));
+        assert_eq!(record(&readings), Some(25.));
+
+        // and the trainer keeps its own offset when it takes back over
+        readings.set_distance(Source::SpeedCadence, None);
+        readings.set_distance(Source::Trainer, Some(1030.));
+        assert_eq!(record(&readings), Some(30.));
+    }
+}
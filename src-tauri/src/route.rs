@@ -0,0 +1,467 @@
+use std::io::BufReader;
+
+use fit_file::fit_file;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::name::LocalName;
+use quick_xml::Reader;
+use ts_rs::TS;
+
+// metres
+const EARTH_RADIUS: f64 = 6371000.;
+
+// elevation is differenced over this distance either side of the rider, in
+// metres, so GPS noise doesn't turn into grade spikes
+const GRADE_WINDOW: f64 = 25.;
+
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, TS)]
+#[ts(export, export_to = "../src/types/RoutePoint.ts")]
+pub struct RoutePoint {
+    // metres from the start
+    pub distance: f64,
+    // metres
+    pub elevation: f64,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, TS)]
+#[ts(export, export_to = "../src/types/Route.ts")]
+pub struct Route {
+    pub name: Option<String>,
+    pub points: Vec<RoutePoint>,
+    pub distance: f64,
+    pub ascent: f64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, TS)]
+#[ts(export, export_to = "../src/types/RoutePosition.ts")]
+pub struct RoutePosition {
+    pub distance: f64,
+    pub remaining_distance: f64,
+    // percent
+    pub grade: f64,
+    pub elevation: f64,
+    pub remaining_ascent: f64,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+}
+
+// A track point before distances have been worked out.
+#[derive(Clone, Copy, Debug, Default)]
+struct TrackPoint {
+    latitude: Option<f64>,
+    longitude: Option<f64>,
+    elevation: Option<f64>,
+    distance: Option<f64>,
+}
+
+impl Route {
+    fn from_track(name: Option<String>, track: Vec<TrackPoint>) -> Result<Route, String> {
+        let mut points: Vec<RoutePoint> = vec![];
+
+        for point in track {
+            let elevation = match point.elevation {
+                Some(elevation) => elevation,
+                None => continue,
+            };
+
+            let distance = match (point.distance, points.last()) {
+                (Some(distance), _) => distance,
+                (None, None) => 0.,
+                (None, Some(last)) => match (
+                    last.latitude,
+                    last.longitude,
+                    point.latitude,
+                    point.longitude,
+                ) {
+                    (Some(lat1), Some(lon1), Some(lat2), Some(lon2)) => {
+                        last.distance + haversine(lat1, lon1, lat2, lon2)
+                    }
+                    _ => return Err("track point has neither distance nor position".into()),
+                },
+            };
+
+            // drop points that don't move the rider along
+            if points.last().is_some_and(|last| distance <= last.distance) {
+                continue;
+            }
+
+            points.push(RoutePoint {
+                distance,
+                elevation,
+                latitude: point.latitude,
+                longitude: point.longitude,
+            });
+        }
+
+        if points.len() < 2 {
+            return Err("route has fewer than two points with elevation".into());
+        }
+
+        let distance = points.last().unwrap().distance - points[0].distance;
+        let start = points[0].distance;
+        for point in points.iter_mut() {
+            point.distance -= start;
+        }
+
+        let ascent = points
+            .windows(2)
+            .map(|w| (w[1].elevation - w[0].elevation).max(0.))
+            .sum();
+
+        Ok(Route {
+            name,
+            points,
+            distance,
+            ascent,
+        })
+    }
+
+    // index of the segment containing the distance, clamped to the route
+    fn segment(&self, distance: f64) -> usize {
+        self.points
+            .partition_point(|p| p.distance <= distance)
+            .clamp(1, self.points.len() - 1)
+            - 1
+    }
+
+    fn interpolate(
+        &self,
+        distance: f64,
+        value: impl Fn(&RoutePoint) -> Option<f64>,
+    ) -> Option<f64> {
+        let distance = distance.clamp(0., self.distance);
+        let i = self.segment(distance);
+        let (a, b) = (&self.points[i], &self.points[i + 1]);
+        let t = (distance - a.distance) / (b.distance - a.distance);
+
+        Some(value(a)? + (value(b)? - value(a)?) * t)
+    }
+
+    pub fn elevation_at(&self, distance: f64) -> f64 {
+        self.interpolate(distance, |p| Some(p.elevation)).unwrap()
+    }
+
+    pub fn grade_at(&self, distance: f64) -> f64 {
+        let start = (distance - GRADE_WINDOW).max(0.);
+        let end = (distance + GRADE_WINDOW).min(self.distance);
+
+        if end <= start {
+            return 0.;
+        }
+
+        (self.elevation_at(end) - self.elevation_at(start)) / (end - start) * 100.
+    }
+
+    pub fn remaining_ascent(&self, distance: f64) -> f64 {
+        let distance = distance.clamp(0., self.distance);
+        let i = self.segment(distance);
+
+        let rest: f64 = self.points[i + 1..]
+            .windows(2)
+            .map(|w| (w[1].elevation - w[0].elevation).max(0.))
+            .sum();
+
+        rest + (self.points[i + 1].elevation - self.elevation_at(distance)).max(0.)
+    }
+
+    pub fn position(&self, distance: f64) -> RoutePosition {
+        let distance = distance.clamp(0., self.distance);
+
+        RoutePosition {
+            distance,
+            remaining_distance: self.distance - distance,
+            grade: self.grade_at(distance),
+            elevation: self.elevation_at(distance),
+            remaining_ascent: self.remaining_ascent(distance),
+            latitude: self.interpolate(distance, |p| p.latitude),
+            longitude: self.interpolate(distance, |p| p.longitude),
+        }
+    }
+}
+
+// Great-circle distance between two positions, in metres.
+fn haversine(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let (lat1, lat2) = (lat1.to_radians(), lat2.to_radians());
+    let dlat = lat2 - lat1;
+    let dlon = (lon2 - lon1).to_radians();
+
+    let a = (dlat / 2.).sin().powi(2) + lat1.cos() * lat2.cos() * (dlon / 2.).sin().powi(2);
+
+    2. * EARTH_RADIUS * a.sqrt().asin()
+}
+
+// elements holding a point of a GPX track or route, or a TCX track
+fn is_point(name: &str) -> bool {
+    matches!(name, "trkpt" | "rtept" | "Trackpoint")
+}
+
+fn local_name(name: LocalName) -> String {
+    String::from_utf8_lossy(name.as_ref()).into_owned()
+}
+
+// GPX points carry their position as attributes
+fn point_from_attributes(element: &BytesStart) -> Result<TrackPoint, String> {
+    let mut point = TrackPoint::default();
+
+    for attribute in element.attributes() {
+        let attribute = attribute.map_err(|e| format!("parse route: {}", e))?;
+        let value = attribute
+            .unescape_value()
+            .map_err(|e| format!("parse route: {}", e))?;
+
+        match attribute.key.local_name().as_ref() {
+            b"lat" => point.latitude = value.trim().parse().ok(),
+            b"lon" => point.longitude = value.trim().parse().ok(),
+            _ => {}
+        }
+    }
+
+    Ok(point)
+}
+
+#[derive(Default)]
+struct XmlRoute {
+    root: Option<String>,
+    path: Vec<String>,
+    name: Option<String>,
+    point: Option<TrackPoint>,
+    track: Vec<TrackPoint>,
+    route: Vec<TrackPoint>,
+}
+
+impl XmlRoute {
+    fn start(&mut self, element: &BytesStart) -> Result<(), String> {
+        let name = local_name(element.local_name());
+
+        if is_point(&name) {
+            self.point = Some(point_from_attributes(element)?);
+        }
+        self.root.get_or_insert_with(|| name.clone());
+        self.path.push(name);
+
+        Ok(())
+    }
+
+    fn end(&mut self) {
+        if !self.path.pop().is_some_and(|name| is_point(&name)) {
+            return;
+        }
+
+        if let Some(point) = self.point.take() {
+            if self.path.last().is_some_and(|p| p == "rte") {
+                self.route.push(point);
+            } else {
+                self.track.push(point);
+            }
+        }
+    }
+
+    fn text(&mut self, text: &str) {
+        let text = text.trim();
+        let n = self.path.len();
+        let element = self.path.last().map_or("", String::as_str);
+        let parent = n.checked_sub(2).map_or("", |i| self.path[i].as_str());
+
+        // the route's own name, not a waypoint's or a lap's
+        if matches!(
+            (parent, element),
+            ("metadata" | "trk" | "rte", "name") | ("Course", "Name")
+        ) {
+            if self.name.is_none() && !text.is_empty() {
+                self.name = Some(text.into());
+            }
+            return;
+        }
+
+        let point = match self.point {
+            Some(ref mut point) => point,
+            None => return,
+        };
+
+        let value = text.parse().ok();
+        match (parent, element) {
+            (_, "ele") | ("Trackpoint", "AltitudeMeters") => point.elevation = value,
+            ("Trackpoint", "DistanceMeters") => point.distance = value,
+            ("Position", "LatitudeDegrees") => point.latitude = value,
+            ("Position", "LongitudeDegrees") => point.longitude = value,
+            _ => {}
+        }
+    }
+}
+
+fn parse_xml(text: &str) -> Result<Route, String> {
+    let mut reader = Reader::from_str(text);
+    reader.trim_text(true);
+
+    let mut xml = XmlRoute::default();
+
+    loop {
+        match reader
+            .read_event()
+            .map_err(|e| format!("parse route: {}", e))?
+        {
+            Event::Start(element) => xml.start(&element)?,
+            Event::Empty(element) => {
+                xml.start(&element)?;
+                xml.end();
+            }
+            Event::End(_) => xml.end(),
+            Event::Text(text) => {
+                xml.text(&text.unescape().map_err(|e| format!("parse route: {}", e))?)
+            }
+            Event::CData(data) => xml.text(&String::from_utf8_lossy(&data)),
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    if !matches!(xml.root.as_deref(), Some("gpx" | "TrainingCenterDatabase")) {
+        return Err("unrecognised route format".into());
+    }
+
+    // GPX files can have both, in which case the recorded track is followed
+    let points = if xml.track.is_empty() {
+        xml.route
+    } else {
+        xml.track
+    };
+
+    Route::from_track(xml.name, points)
+}
+
+fn semicircles_to_degrees(semicircles: i32) -> f64 {
+    semicircles as f64 * 180. / 2f64.powi(31)
+}
+
+fn fit_message_callback(
+    _timestamp: u32,
+    global_msg_num: u16,
+    _local_msg_num: u8,
+    _message_index: u16,
+    fields: Vec<fit_file::FitFieldValue>,
+    data: &mut Vec<TrackPoint>,
+) {
+    if global_msg_num == fit_file::GLOBAL_MSG_NUM_RECORD {
+        let record = fit_file::FitRecordMsg::new(fields);
+
+        // altitude is in 1/5 m offset by 500 m, distance in centimetres
+        let altitude = record
+            .enhanced_altitude
+            .or(record.altitude.map(u32::from))
+            .map(|a| a as f64 / 5. - 500.);
+
+        data.push(TrackPoint {
+            latitude: record.position_lat.map(semicircles_to_degrees),
+            longitude: record.position_long.map(semicircles_to_degrees),
+            elevation: altitude,
+            distance: record.distance.map(|d| d as f64 / 100.),
+        });
+    }
+}
+
+fn parse_fit(data: &[u8]) -> Result<Route, String> {
+    let mut points = vec![];
+    let mut reader = BufReader::new(data);
+
+    fit_file::read(&mut reader, fit_message_callback, &mut points)
+        .map_err(|e| format!("reading fit file: {}", e))?;
+
+    Route::from_track(None, points)
+}
+
+pub fn load_route(data: &[u8]) -> Result<Route, String> {
+    if data.get(8..12) == Some(b".FIT") {
+        return parse_fit(data);
+    }
+
+    let text = std::str::from_utf8(data).map_err(|e| format!("decode route: {}", e))?;
+
+    parse_xml(text)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::route::{self, Route};
+    use crate::workout;
+    use base64::{engine::general_purpose, Engine as _};
+
+    // four points 100 m apart climbing 15 m, then dropping 5 m
+    fn assert_test_climb(route: &Route) {
+        assert_eq!(route.points.len(), 4);
+        assert!(
+            (route.distance - 300.).abs() < 0.5,
+            "distance: {}",
+            route.distance
+        );
+        assert!((route.ascent - 15.).abs() < 0.01);
+
+        // 10 m over 100 m in the middle of the second segment
+        assert!((route.grade_at(150.) - 10.).abs() < 0.1);
+        assert!((route.remaining_ascent(150.) - 5.).abs() < 0.1);
+        assert_eq!(route.position(1000.).remaining_distance, 0.);
+    }
+
+    #[test]
+    fn it_loads_gpx_and_derives_grade() {
+        let gpx = r#"<?xml version="1.0"?>
+<gpx version="1.1" xmlns="http://www.topografix.com/GPX/1/1">
+  <metadata><time>2023-10-01T10:00:00Z</time></metadata>
+  <wpt lat="45.0018" lon="6.0"><name>Summit</name></wpt>
+  <trk><name><![CDATA[Test & climb]]></name><trkseg>
+    <!-- <trkpt lat="0" lon="0"><ele>0</ele></trkpt> -->
+    <trkpt lat = "45.0" lon = "6.0"><ele>100</ele></trkpt>
+    <trkpt lat='45.0009' lon='6.0'><ele>105</ele></trkpt>
+    <trkpt lat="45.0018" lon="6.0"><ele> 115 </ele></trkpt>
+    <trkpt lon="6.0" lat="45.0027"><ele>110</ele><name>Top</name></trkpt>
+  </trkseg></trk>
+</gpx>"#;
+
+        // file pickers label GPX with its own media type
+        let url = format!(
+            "data:application/gpx+xml;base64,{}",
+            general_purpose::STANDARD.encode(gpx)
+        );
+        let data = workout::decode_data_url(&url, None).expect("data URL decodes");
+        let route = route::load_route(&data).expect("route loads");
+
+        assert_eq!(route.name.as_deref(), Some("Test & climb"));
+        assert_test_climb(&route);
+    }
+
+    #[test]
+    fn it_loads_tcx_course_distances() {
+        let tcx = r#"<?xml version="1.0" encoding="UTF-8"?>
+<TrainingCenterDatabase xmlns="http://www.garmin.com/xmlschemas/TrainingCenterDatabase/v2">
+  <Courses><Course>
+    <Name>Test climb</Name>
+    <Lap><DistanceMeters>300</DistanceMeters></Lap>
+    <Track>
+      <Trackpoint><DistanceMeters>0</DistanceMeters><AltitudeMeters>100</AltitudeMeters></Trackpoint>
+      <Trackpoint>
+        <Position><LatitudeDegrees>45.0009</LatitudeDegrees><LongitudeDegrees>6.0</LongitudeDegrees></Position>
+        <AltitudeMeters>105</AltitudeMeters><DistanceMeters>100</DistanceMeters>
+      </Trackpoint>
+      <Trackpoint><DistanceMeters>200</DistanceMeters><AltitudeMeters>115</AltitudeMeters></Trackpoint>
+      <Trackpoint><DistanceMeters>300</DistanceMeters><AltitudeMeters>110</AltitudeMeters></Trackpoint>
+    </Track>
+  </Course></Courses>
+</TrainingCenterDatabase>"#;
+
+        let route = route::load_route(tcx.as_bytes()).expect("route loads");
+
+        assert_eq!(route.name.as_deref(), Some("Test climb"));
+        assert_eq!(route.points[1].latitude, Some(45.0009));
+        assert_test_climb(&route);
+    }
+
+    #[test]
+    fn it_loads_fit_course() {
+        let data = std::fs::read("./tests/fixtures/course.fit").expect("file loads");
+
+        let route = route::load_route(&data).expect("route loads");
+
+        assert!((route.points[3].latitude.unwrap() - 45.0027).abs() < 1e-6);
+        assert_test_climb(&route);
+    }
+}
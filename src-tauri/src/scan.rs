@@ -0,0 +1,342 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use antrs::device::DevicePairing;
+use antrs::message::{Message, MessageID};
+use antrs::node;
+use antrs::profile::generic;
+use log::{debug, error, warn};
+use ts_rs::TS;
+
+const COMMON_PAGE_MANUFACTURER_INFORMATION: u8 = 80;
+const HRM_PAGE_MANUFACTURER_INFORMATION: u8 = 2;
+
+// how long to keep listening to a found device for its manufacturer before
+// searching for the next one
+const IDENTIFY_TIMEOUT: Duration = Duration::from_secs(5);
+
+// lib config flags adding the channel ID and RSSI to received messages
+const EXTENDED_CHANNEL_ID: u8 = 0x80;
+const EXTENDED_RSSI: u8 = 0x40;
+
+// the channel ID list holds at most this many devices
+const MAX_EXCLUDED: usize = 4;
+
+// pause before searching again when the search finds a device that was
+// already reported, so it doesn't spin on the same one
+const SEARCH_BACKOFF: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize, TS)]
+#[serde(rename_all = "snake_case")]
+#[ts(export, export_to = "../src/types/DeviceKind.ts")]
+pub enum DeviceKind {
+    HeartRateMonitor,
+    FitnessEquipment,
+    BicyclePower,
+    BikeSpeedCadence,
+    BikeSpeed,
+    BikeCadence,
+}
+
+impl DeviceKind {
+    pub const ALL: [DeviceKind; 6] = [
+        DeviceKind::HeartRateMonitor,
+        DeviceKind::FitnessEquipment,
+        DeviceKind::BicyclePower,
+        DeviceKind::BikeSpeedCadence,
+        DeviceKind::BikeSpeed,
+        DeviceKind::BikeCadence,
+    ];
+
+    pub fn device_type(&self) -> u8 {
+        match self {
+            DeviceKind::HeartRateMonitor => 120,
+            DeviceKind::FitnessEquipment => 17,
+            DeviceKind::BicyclePower => 11,
+            DeviceKind::BikeSpeedCadence => 121,
+            DeviceKind::BikeSpeed => 123,
+            DeviceKind::BikeCadence => 122,
+        }
+    }
+
+    pub fn channel_period(&self) -> u16 {
+        match self {
+            DeviceKind::HeartRateMonitor => 8070,
+            DeviceKind::FitnessEquipment => 8192,
+            DeviceKind::BicyclePower => 8182,
+            DeviceKind::BikeSpeedCadence => 8086,
+            DeviceKind::BikeSpeed => 8118,
+            DeviceKind::BikeCadence => 8102,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, TS)]
+#[ts(export, export_to = "../src/types/FoundDevice.ts")]
+pub struct FoundDevice {
+    pub kind: DeviceKind,
+    pub device_id: u16,
+    pub transmission_type: u8,
+    // dBm
+    pub rssi: Option<i8>,
+    pub manufacturer: Option<u16>,
+}
+
+pub fn manufacturer_from_page(kind: DeviceKind, payload: &[u8; 8]) -> Option<u16> {
+    // the HRM profile toggles the high bit of the page number
+    let page = payload[0] & 0x7f;
+
+    if page == COMMON_PAGE_MANUFACTURER_INFORMATION {
+        Some(u16::from_le_bytes([payload[4], payload[5]]))
+    } else if kind == DeviceKind::HeartRateMonitor && page == HRM_PAGE_MANUFACTURER_INFORMATION {
+        Some(payload[1] as u16)
+    } else {
+        None
+    }
+}
+
+fn request_channel_id(node: &node::Node, channel: u8) -> Result<(u16, u8), node::Error> {
+    let matcher = move |message| {
+        if let Message::ChannelID(data) = message {
+            data.channel == channel
+        } else {
+            false
+        }
+    };
+
+    let response =
+        node.wait_for_message_after(Box::new(matcher), Duration::from_millis(400), || {
+            node.write_message(
+                antrs::message::request_message(channel, MessageID::ChannelID),
+                Duration::from_millis(100),
+            )
+        })?;
+
+    match response {
+        Message::ChannelID(data) => Ok((data.device_number, data.transmission_type)),
+        _ => Err(node::Error::Timeout),
+    }
+}
+
+// Asks the node to add the sender's channel ID and signal strength to the
+// messages it receives.
+pub fn enable_extended_data(node: &node::Node) -> Result<(), String> {
+    node.write_message(
+        antrs::message::lib_config(EXTENDED_CHANNEL_ID | EXTENDED_RSSI),
+        Duration::from_millis(100),
+    )
+    .map_err(|e| format!("enabling extended data: {}", e))
+}
+
+// Opens a wildcard search that leaves out the most recently found devices.
+fn open_search(
+    node: &Mutex<Option<node::Node>>,
+    kind: DeviceKind,
+    found: &[FoundDevice],
+) -> Result<(u8, crossbeam_channel::Receiver<generic::ExtendedData>), String> {
+    let wildcard = DevicePairing {
+        device_id: 0,
+        transmission_type: 0,
+    };
+
+    let (device, receiver) =
+        generic::new_paired_extended(kind.device_type(), kind.channel_period(), wildcard);
+
+    let mut node = node.lock().unwrap();
+    let node = match *node {
+        Some(ref mut node) => node,
+        None => return Err("node not open".into()),
+    };
+
+    let channel = node
+        .assign_channel(
+            Box::new(device),
+            Some(node::ChannelOptions {
+                low_priority_search_timeout: Some(255),
+                search_timeout: Some(0),
+            }),
+        )
+        .map_err(|e| format!("assigning channel: {}", e))?;
+
+    let excluded = &found[found.len().saturating_sub(MAX_EXCLUDED)..];
+    if !excluded.is_empty() {
+        let result = excluded
+            .iter()
+            .enumerate()
+            .try_for_each(|(index, device)| {
+                node.write_message(
+                    antrs::message::add_channel_id_to_list(
+                        channel,
+                        device.device_id,
+                        kind.device_type(),
+                        device.transmission_type,
+                        index as u8,
+                    ),
+                    Duration::from_millis(100),
+                )
+            })
+            .and_then(|_| {
+                node.write_message(
+                    antrs::message::config_id_list(channel, excluded.len() as u8, true),
+                    Duration::from_millis(100),
+                )
+            });
+
+        // devices already found are still passed over, just more slowly
+        if let Err(e) = result {
+            warn!("excluding found {:?} devices: {}", kind, e);
+        }
+    }
+
+    Ok((channel, receiver))
+}
+
+enum Search {
+    Found(FoundDevice),
+    AlreadyFound,
+    TimedOut,
+}
+
+// Listens on a search channel until it identifies a device, waiting a little
+// longer for its manufacturer once it has.
+fn identify(
+    node: &Mutex<Option<node::Node>>,
+    kind: DeviceKind,
+    channel: u8,
+    receiver: &crossbeam_channel::Receiver<generic::ExtendedData>,
+    deadline: Instant,
+    found: &[FoundDevice],
+) -> Result<Search, String> {
+    let mut device: Option<FoundDevice> = None;
+    let mut until = deadline;
+
+    while let Some(remaining) = until.checked_duration_since(Instant::now()) {
+        let data = match receiver.recv_timeout(remaining) {
+            Ok(data) => data,
+            Err(_) => break,
+        };
+
+        if device.is_none() {
+            let channel_id = match (data.device_number, data.transmission_type) {
+                (Some(device_number), Some(transmission_type)) => {
+                    Ok((device_number, transmission_type))
+                }
+                // without extended data the channel ID has to be asked for
+                _ => {
+                    let node = node.lock().unwrap();
+                    match *node {
+                        Some(ref node) => request_channel_id(node, channel),
+                        None => return Err("node not open".into()),
+                    }
+                }
+            };
+
+            let (device_id, transmission_type) = match channel_id {
+                Ok(channel_id) => channel_id,
+                Err(e) => {
+                    warn!("requesting channel id for {:?}: {}", kind, e);
+                    continue;
+                }
+            };
+
+            if found.iter().any(|d| d.device_id == device_id) {
+                return Ok(Search::AlreadyFound);
+            }
+
+            device = Some(FoundDevice {
+                kind,
+                device_id,
+                transmission_type,
+                rssi: None,
+                manufacturer: None,
+            });
+            until = deadline.min(Instant::now() + IDENTIFY_TIMEOUT);
+        }
+
+        if let Some(ref mut device) = device {
+            device.rssi = data.rssi.or(device.rssi);
+            device.manufacturer =
+                manufacturer_from_page(kind, &data.payload).or(device.manufacturer);
+
+            if device.manufacturer.is_some() {
+                break;
+            }
+        }
+    }
+
+    Ok(device.map_or(Search::TimedOut, Search::Found))
+}
+
+// Reports devices of the given kind until the deadline passes. A wildcard
+// search channel locks on to the first device it hears, so once a device is
+// identified the search is opened again with the devices found so far left
+// out of it.
+pub fn scan(
+    node: Arc<Mutex<Option<node::Node>>>,
+    kind: DeviceKind,
+    deadline: Instant,
+    on_found: impl Fn(FoundDevice),
+) -> Result<(), String> {
+    let mut found: Vec<FoundDevice> = vec![];
+
+    while Instant::now() < deadline {
+        let (channel, receiver) = open_search(&node, kind, &found)?;
+
+        debug!("scanning for {:?} on channel {}", kind, channel);
+
+        let search = identify(&node, kind, channel, &receiver, deadline, &found);
+
+        {
+            let mut node = node.lock().unwrap();
+            if let Some(ref mut node) = *node {
+                if let Err(e) = node.close_channel(channel) {
+                    error!("closing scan channel {}: {}", channel, e);
+                }
+            }
+        }
+
+        match search? {
+            Search::Found(device) => {
+                on_found(device);
+                found.push(device);
+            }
+            Search::AlreadyFound => {
+                if let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+                    std::thread::sleep(remaining.min(SEARCH_BACKOFF));
+                }
+            }
+            Search::TimedOut => break,
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::scan::{self, DeviceKind};
+
+    #[test]
+    fn it_reads_manufacturer_from_common_page() {
+        let payload = [0x50, 0xff, 0xff, 0x01, 0x59, 0x00, 0x01, 0x00];
+
+        assert_eq!(
+            scan::manufacturer_from_page(DeviceKind::BicyclePower, &payload),
+            Some(89)
+        );
+        assert_eq!(
+            scan::manufacturer_from_page(DeviceKind::BicyclePower, &[0x10; 8]),
+            None
+        );
+    }
+
+    #[test]
+    fn it_reads_manufacturer_from_toggled_hrm_page() {
+        let payload = [0x82, 0x01, 0x02, 0x03, 0x00, 0x00, 0x00, 0x4a];
+
+        assert_eq!(
+            scan::manufacturer_from_page(DeviceKind::HeartRateMonitor, &payload),
+            Some(1)
+        );
+    }
+}
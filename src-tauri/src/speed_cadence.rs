@@ -0,0 +1,123 @@
+use ts_rs::TS;
+
+use crate::scan::DeviceKind;
+
+// sensors broadcast at about 4 Hz, so this is roughly 3 seconds without a new
+// revolution before reporting that the wheel or crank has stopped
+const STOPPED_AFTER_MESSAGES: u32 = 12;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, serde::Serialize, TS)]
+#[ts(export, export_to = "../src/types/SpeedCadenceUpdate.ts")]
+pub struct SpeedCadenceUpdate {
+    // metres per second
+    pub speed: Option<f64>,
+    pub cadence: Option<u8>,
+}
+
+// Revolution rate from an accumulated revolution count and the time of the
+// last revolution event, in 1/1024 s.
+#[derive(Debug, Default)]
+struct RevolutionCounter {
+    last: Option<(u16, u16)>,
+    unchanged: u32,
+    rate: Option<f64>,
+}
+
+impl RevolutionCounter {
+    fn update(&mut self, event_time: u16, revolutions: u16) -> Option<f64> {
+        if let Some((last_time, last_revolutions)) = self.last {
+            let time = event_time.wrapping_sub(last_time);
+            let count = revolutions.wrapping_sub(last_revolutions);
+
+            if time == 0 || count == 0 {
+                self.unchanged += 1;
+                if self.unchanged >= STOPPED_AFTER_MESSAGES {
+                    self.rate = Some(0.);
+                }
+            } else {
+                self.unchanged = 0;
+                self.rate = Some(count as f64 / (time as f64 / 1024.));
+            }
+        }
+
+        self.last = Some((event_time, revolutions));
+        self.rate
+    }
+}
+
+pub struct SpeedCadenceDecoder {
+    kind: DeviceKind,
+    wheel_circumference: f64,
+    wheel: RevolutionCounter,
+    crank: RevolutionCounter,
+}
+
+impl SpeedCadenceDecoder {
+    pub fn new(kind: DeviceKind, wheel_circumference: f64) -> SpeedCadenceDecoder {
+        SpeedCadenceDecoder {
+            kind,
+            wheel_circumference,
+            wheel: RevolutionCounter::default(),
+            crank: RevolutionCounter::default(),
+        }
+    }
+
+    pub fn decode(&mut self, payload: &[u8; 8]) -> SpeedCadenceUpdate {
+        let field = |i: usize| u16::from_le_bytes([payload[i], payload[i + 1]]);
+
+        let (wheel, crank) = match self.kind {
+            // the combined profile has no data pages, just both sets of counters
+            DeviceKind::BikeSpeedCadence => (
+                self.wheel.update(field(4), field(6)),
+                self.crank.update(field(0), field(2)),
+            ),
+            DeviceKind::BikeSpeed => (self.wheel.update(field(4), field(6)), None),
+            DeviceKind::BikeCadence => (None, self.crank.update(field(4), field(6))),
+            _ => (None, None),
+        };
+
+        SpeedCadenceUpdate {
+            speed: wheel.map(|rate| rate * self.wheel_circumference),
+            cadence: crank.map(|rate| (rate * 60.).round().min(254.) as u8),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::scan::DeviceKind;
+    use crate::speed_cadence::SpeedCadenceDecoder;
+
+    #[test]
+    fn it_decodes_combined_speed_and_cadence() {
+        let mut decoder = SpeedCadenceDecoder::new(DeviceKind::BikeSpeedCadence, 2.1);
+
+        decoder.decode(&[0x55, 0xfd, 0xff, 0xff, 0x00, 0xfc, 0xfe, 0xff]);
+
+        // one crank revolution in 2/3 s and four wheel revolutions in 1 s,
+        // both across a counter rollover
+        let update = decoder.decode(&[0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x00]);
+
+        assert_eq!(update.cadence, Some(90));
+        assert!((update.speed.unwrap() - 8.4).abs() < 1e-9);
+    }
+
+    #[test]
+    fn it_reports_zero_once_the_wheel_stops() {
+        let mut decoder = SpeedCadenceDecoder::new(DeviceKind::BikeSpeed, 2.1);
+
+        decoder.decode(&[0x00, 0, 0, 0, 0x00, 0x00, 0x00, 0x00]);
+        let update = decoder.decode(&[0x00, 0, 0, 0, 0x00, 0x04, 0x01, 0x00]);
+        assert!(update.speed.unwrap() > 0.);
+
+        for _ in 0..12 {
+            decoder.decode(&[0x00, 0, 0, 0, 0x00, 0x04, 0x01, 0x00]);
+        }
+        assert_eq!(
+            decoder
+                .decode(&[0x00, 0, 0, 0, 0x00, 0x04, 0x01, 0x00])
+                .speed,
+            Some(0.)
+        );
+    }
+}
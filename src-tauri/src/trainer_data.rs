@@ -0,0 +1,419 @@
+use ts_rs::TS;
+
+use crate::config::{RiderProfile, Simulation};
+use crate::workout::{StepMode, Workout};
+
+pub const PAGE_GENERAL_FE_DATA: u8 = 16;
+pub const PAGE_BASIC_RESISTANCE: u8 = 48;
+pub const PAGE_WIND_RESISTANCE: u8 = 50;
+pub const PAGE_TRACK_RESISTANCE: u8 = 51;
+pub const PAGE_CAPABILITIES: u8 = 54;
+pub const PAGE_USER_CONFIGURATION: u8 = 55;
+pub const PAGE_COMMAND_STATUS: u8 = 71;
+
+// the FE-C target power page can't request more than this
+pub const MAX_TARGET_POWER: u16 = 4000;
+
+const CAPABILITY_BASIC_RESISTANCE: u8 = 0x01;
+const CAPABILITY_TARGET_POWER: u8 = 0x02;
+const CAPABILITY_SIMULATION: u8 = 0x04;
+
+const SPEED_INVALID: u16 = 0xffff;
+
+// trainer status bits from the specific trainer data page
+const STATUS_POWER_CALIBRATION_REQUIRED: u8 = 0x01;
+const STATUS_RESISTANCE_CALIBRATION_REQUIRED: u8 = 0x02;
+const STATUS_USER_CONFIGURATION_REQUIRED: u8 = 0x04;
+
+// FE state field of the general FE data page
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, TS)]
+#[serde(rename_all = "snake_case")]
+#[ts(export, export_to = "../src/types/FeState.ts")]
+pub enum FeState {
+    #[default]
+    Unknown,
+    Asleep,
+    Ready,
+    InUse,
+    Finished,
+}
+
+impl From<u8> for FeState {
+    fn from(value: u8) -> Self {
+        match value {
+            1 => FeState::Asleep,
+            2 => FeState::Ready,
+            3 => FeState::InUse,
+            4 => FeState::Finished,
+            _ => FeState::Unknown,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, serde::Serialize, TS)]
+#[ts(export, export_to = "../src/types/TrainerUpdate.ts")]
+pub struct TrainerUpdate {
+    // seconds
+    pub elapsed: f64,
+    // metres
+    pub distance: f64,
+    // metres per second
+    pub speed: Option<f64>,
+    pub state: FeState,
+    pub power_calibration_required: bool,
+    pub resistance_calibration_required: bool,
+    pub user_configuration_required: bool,
+}
+
+impl TrainerUpdate {
+    pub fn calibration_required(&self) -> bool {
+        self.power_calibration_required || self.resistance_calibration_required
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, TS)]
+#[ts(export, export_to = "../src/types/Capabilities.ts")]
+pub struct Capabilities {
+    // newtons
+    pub maximum_resistance: Option<u16>,
+    pub basic_resistance: bool,
+    pub target_power: bool,
+    pub simulation: bool,
+}
+
+impl Capabilities {
+    // The most power the trainer can absorb at a speed in metres per second,
+    // its maximum resistance force times the speed.
+    pub fn max_power(&self, speed: f64) -> u16 {
+        self.maximum_resistance
+            .map_or(MAX_TARGET_POWER, |resistance| {
+                (resistance as f64 * speed).min(MAX_TARGET_POWER as f64) as u16
+            })
+    }
+}
+
+pub fn decode_capabilities(payload: &[u8; 8]) -> Option<Capabilities> {
+    if payload[0] != PAGE_CAPABILITIES {
+        return None;
+    }
+
+    let maximum_resistance = u16::from_le_bytes([payload[5], payload[6]]);
+
+    Some(Capabilities {
+        maximum_resistance: (maximum_resistance != 0xffff).then_some(maximum_resistance),
+        basic_resistance: payload[7] & CAPABILITY_BASIC_RESISTANCE != 0,
+        target_power: payload[7] & CAPABILITY_TARGET_POWER != 0,
+        simulation: payload[7] & CAPABILITY_SIMULATION != 0,
+    })
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, TS)]
+#[ts(export, export_to = "../src/types/WorkoutWarning.ts")]
+pub struct WorkoutWarning {
+    pub step_index: Option<usize>,
+    pub message: String,
+}
+
+pub fn check_workout(workout: &Workout, capabilities: Option<Capabilities>) -> Vec<WorkoutWarning> {
+    let mut warnings = vec![];
+
+    for (step_index, step) in workout.steps.iter().enumerate() {
+        if step.mode == StepMode::Erg && step.set_point > MAX_TARGET_POWER as u32 {
+            warnings.push(WorkoutWarning {
+                step_index: Some(step_index),
+                message: format!(
+                    "target of {} W is above the FE-C limit of {} W and will be limited",
+                    step.set_point, MAX_TARGET_POWER
+                ),
+            });
+        }
+    }
+
+    let capabilities = match capabilities {
+        Some(capabilities) => capabilities,
+        None => {
+            warnings.push(WorkoutWarning {
+                step_index: None,
+                message: "trainer capabilities are unknown".into(),
+            });
+            return warnings;
+        }
+    };
+
+    if !capabilities.target_power {
+        warnings.push(WorkoutWarning {
+            step_index: None,
+            message: "trainer does not support target power mode".into(),
+        });
+    }
+
+    if !capabilities.simulation
+        && workout
+            .steps
+            .iter()
+            .any(|step| step.mode == StepMode::Simulation)
+    {
+        warnings.push(WorkoutWarning {
+            step_index: None,
+            message: "trainer does not support simulation mode".into(),
+        });
+    }
+
+    if !capabilities.basic_resistance
+        && workout
+            .steps
+            .iter()
+            .any(|step| step.mode == StepMode::Resistance)
+    {
+        warnings.push(WorkoutWarning {
+            step_index: None,
+            message: "trainer does not support basic resistance mode".into(),
+        });
+    }
+
+    warnings
+}
+
+// The wheel diameter is worked out from the configured circumference, in metres.
+pub fn user_configuration_payload(rider: &RiderProfile, wheel_circumference: f64) -> [u8; 8] {
+    let rider_weight = ((rider.rider_weight * 100.).round() as u16).min(0xfffe);
+    let bike_weight = ((rider.bike_weight / 0.05).round() as u16).min(0x0ffe);
+    let wheel_millimetres = (wheel_circumference / std::f64::consts::PI * 1000.).round() as u16;
+    // the diameter is sent in centimetres with the remaining millimetres as an offset
+    let wheel_diameter = (wheel_millimetres / 10).min(254) as u8;
+    let wheel_offset = (wheel_millimetres % 10) as u8;
+    let gear_ratio = rider
+        .gear_ratio
+        .map_or(0, |ratio| (ratio / 0.03).round().clamp(1., 255.) as u8);
+
+    let rider_weight = rider_weight.to_le_bytes();
+
+    [
+        PAGE_USER_CONFIGURATION,
+        rider_weight[0],
+        rider_weight[1],
+        0xff,
+        wheel_offset | ((bike_weight & 0x0f) as u8) << 4,
+        (bike_weight >> 4) as u8,
+        wheel_diameter,
+        gear_ratio,
+    ]
+}
+
+// Resistance in percent of the trainer's maximum, sent in 0.5% units.
+pub fn basic_resistance_payload(resistance: f64) -> [u8; 8] {
+    let resistance = (resistance.clamp(0., 100.) * 2.).round() as u8;
+
+    [
+        PAGE_BASIC_RESISTANCE,
+        0xff,
+        0xff,
+        0xff,
+        0xff,
+        0xff,
+        0xff,
+        resistance,
+    ]
+}
+
+// Grade in percent, sent in 0.01% units offset by -200%.
+pub fn track_resistance_payload(grade: f64, simulation: &Simulation) -> [u8; 8] {
+    let grade = ((grade.clamp(-200., 200.) + 200.) * 100.).round() as u16;
+    let rolling_resistance = (simulation.rolling_resistance / 5e-5).round().min(254.) as u8;
+
+    let grade = grade.to_le_bytes();
+
+    [
+        PAGE_TRACK_RESISTANCE,
+        0xff,
+        0xff,
+        0xff,
+        0xff,
+        grade[0],
+        grade[1],
+        rolling_resistance,
+    ]
+}
+
+pub fn wind_resistance_payload(simulation: &Simulation) -> [u8; 8] {
+    let coefficient = (simulation.wind_resistance_coefficient() * 100.)
+        .round()
+        .min(254.) as u8;
+    // still air, in km/h offset by -127
+    let wind_speed = 127;
+    let drafting_factor = (simulation.drafting_factor * 100.).round().clamp(0., 100.) as u8;
+
+    [
+        PAGE_WIND_RESISTANCE,
+        0xff,
+        0xff,
+        0xff,
+        0xff,
+        coefficient,
+        wind_speed,
+        drafting_factor,
+    ]
+}
+
+// Accumulates general FE data and trainer status. Elapsed time and distance
+// are broadcast as single bytes that roll over, so they're summed from the
+// change between pages.
+#[derive(Debug, Default)]
+pub struct TrainerDataDecoder {
+    last_elapsed: Option<u8>,
+    last_distance: Option<u8>,
+    elapsed: u32,
+    distance: u32,
+    current: TrainerUpdate,
+}
+
+impl TrainerDataDecoder {
+    pub fn decode_general(&mut self, payload: &[u8; 8]) -> Option<TrainerUpdate> {
+        if payload[0] != PAGE_GENERAL_FE_DATA {
+            return None;
+        }
+
+        if let Some(last) = self.last_elapsed {
+            self.elapsed += payload[2].wrapping_sub(last) as u32;
+        }
+        self.last_elapsed = Some(payload[2]);
+
+        if let Some(last) = self.last_distance {
+            self.distance += payload[3].wrapping_sub(last) as u32;
+        }
+        self.last_distance = Some(payload[3]);
+
+        let speed = u16::from_le_bytes([payload[4], payload[5]]);
+
+        self.current.elapsed = self.elapsed as f64 / 4.;
+        self.current.distance = self.distance as f64;
+        self.current.speed = (speed != SPEED_INVALID).then_some(speed as f64 / 1000.);
+        self.current.state = (payload[7] >> 4 & 0x07).into();
+
+        Some(self.current)
+    }
+
+    pub fn decode_trainer_status(&mut self, trainer_status: u8) -> TrainerUpdate {
+        self.current.power_calibration_required =
+            trainer_status & STATUS_POWER_CALIBRATION_REQUIRED != 0;
+        self.current.resistance_calibration_required =
+            trainer_status & STATUS_RESISTANCE_CALIBRATION_REQUIRED != 0;
+        self.current.user_configuration_required =
+            trainer_status & STATUS_USER_CONFIGURATION_REQUIRED != 0;
+
+        self.current
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::config::{RiderProfile, Simulation};
+    use crate::trainer_data::{self, FeState, TrainerDataDecoder};
+    use crate::workout::{StepMode, Workout, WorkoutStep};
+
+    #[test]
+    fn it_accumulates_elapsed_time_and_distance_across_rollover() {
+        let mut decoder = TrainerDataDecoder::default();
+
+        decoder.decode_general(&[16, 25, 250, 200, 0x00, 0x00, 0xff, 0x30]);
+        let update = decoder
+            .decode_general(&[16, 25, 6, 20, 0x94, 0x22, 0xff, 0x30])
+            .expect("page decodes");
+
+        assert_eq!(update.elapsed, 3.);
+        assert_eq!(update.distance, 76.);
+        assert_eq!(update.speed, Some(8.852));
+        assert_eq!(update.state, FeState::InUse);
+
+        let update = decoder.decode_trainer_status(0x01);
+        assert!(update.calibration_required());
+        assert!(!update.user_configuration_required);
+    }
+
+    #[test]
+    fn it_warns_about_steps_above_the_target_power_limit() {
+        let capabilities =
+            trainer_data::decode_capabilities(&[54, 0xff, 0xff, 0xff, 0xff, 0x32, 0x00, 0x07])
+                .expect("page decodes");
+        assert_eq!(capabilities.maximum_resistance, Some(50));
+        // 50 N at 8 m/s
+        assert_eq!(capabilities.max_power(8.), 400);
+        assert!(capabilities.simulation);
+
+        let step = |set_point| WorkoutStep {
+            mode: StepMode::Erg,
+            set_point,
+            target_power: (set_point, set_point),
+            target_cadence: None,
+            target_grade: None,
+            target_resistance: None,
+            duration: 60,
+        };
+        let workout = Workout {
+            title: "test".into(),
+            steps: vec![step(200), step(4500)],
+        };
+
+        let warnings = trainer_data::check_workout(&workout, Some(capabilities));
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].step_index, Some(1));
+    }
+
+    #[test]
+    fn it_encodes_basic_resistance() {
+        // in 0.5% units, clamped to the trainer's range
+        assert_eq!(
+            trainer_data::basic_resistance_payload(37.3),
+            [48, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 75]
+        );
+        assert_eq!(trainer_data::basic_resistance_payload(120.)[7], 200);
+        assert_eq!(trainer_data::basic_resistance_payload(-5.)[7], 0);
+    }
+
+    #[test]
+    fn it_encodes_track_resistance() {
+        let simulation = Simulation::default();
+
+        // 0.01% units offset by -200%, then rolling resistance in 5e-5 units
+        assert_eq!(
+            trainer_data::track_resistance_payload(5., &simulation),
+            [51, 0xff, 0xff, 0xff, 0xff, 0x14, 0x50, 80]
+        );
+        assert_eq!(
+            trainer_data::track_resistance_payload(-2.5, &simulation),
+            [51, 0xff, 0xff, 0xff, 0xff, 0x26, 0x4d, 80]
+        );
+    }
+
+    #[test]
+    fn it_encodes_wind_resistance() {
+        let simulation = Simulation {
+            drafting_factor: 0.75,
+            ..Simulation::default()
+        };
+
+        // 0.4 m² of drag area is 0.51 kg/m, in still air
+        assert_eq!(
+            trainer_data::wind_resistance_payload(&simulation),
+            [50, 0xff, 0xff, 0xff, 0xff, 51, 127, 75]
+        );
+    }
+
+    #[test]
+    fn it_encodes_user_configuration() {
+        let payload = trainer_data::user_configuration_payload(
+            &RiderProfile {
+                rider_weight: 75.5,
+                bike_weight: 8.2,
+                gear_ratio: None,
+                drag_area: None,
+                rolling_resistance: None,
+            },
+            2.13,
+        );
+
+        // 75.5 kg in 0.01 kg, 8.2 kg in 0.05 kg and a 2.13 m circumference as
+        // 67 cm plus 8 mm
+        assert_eq!(payload, [55, 0x7e, 0x1d, 0xff, 0x48, 0x0a, 67, 0]);
+    }
+}
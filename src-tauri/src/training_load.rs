@@ -0,0 +1,128 @@
+use std::collections::BTreeMap;
+
+use ts_rs::TS;
+
+const CHRONIC_TIME_CONSTANT: f64 = 42.;
+const ACUTE_TIME_CONSTANT: f64 = 7.;
+
+pub const SECONDS_PER_DAY: u64 = 86400;
+
+#[derive(Clone, Debug, serde::Deserialize, TS)]
+#[ts(export, export_to = "../src/types/PlannedWorkout.ts")]
+pub struct PlannedWorkout {
+    pub days_from_today: u32,
+    pub training_stress: f64,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, TS)]
+#[ts(export, export_to = "../src/types/TrainingLoadDay.ts")]
+pub struct TrainingLoadDay {
+    #[ts(type = "number")]
+    pub date: u64,
+    pub training_stress: f64,
+    pub chronic_load: f64,
+    pub acute_load: f64,
+    pub balance: f64,
+    pub projected: bool,
+}
+
+// Computes daily chronic training load (fitness), acute training load (fatigue)
+// and training stress balance (form) for the `days` days up to and including
+// `today`, followed by a projection over any planned workouts. Balance is the
+// form going into the day, i.e. the difference between yesterday's loads.
+pub fn training_load(
+    rides: &[(u64, f64)],
+    today: u64,
+    days: u32,
+    planned: &[PlannedWorkout],
+) -> Vec<TrainingLoadDay> {
+    let today = today / SECONDS_PER_DAY;
+    let first_reported = today.saturating_sub(days.saturating_sub(1) as u64);
+
+    let mut stress = BTreeMap::new();
+    for &(start_time, training_stress) in rides {
+        *stress.entry(start_time / SECONDS_PER_DAY).or_insert(0.) += training_stress;
+    }
+    for workout in planned {
+        *stress
+            .entry(today + workout.days_from_today as u64)
+            .or_insert(0.) += workout.training_stress;
+    }
+
+    let first_day = stress
+        .keys()
+        .next()
+        .map_or(first_reported, |&first| first.min(first_reported));
+    let last_day = planned
+        .iter()
+        .map(|w| today + w.days_from_today as u64)
+        .max()
+        .map_or(today, |last| last.max(today));
+
+    let chronic_decay = 1. - (-1. / CHRONIC_TIME_CONSTANT).exp();
+    let acute_decay = 1. - (-1. / ACUTE_TIME_CONSTANT).exp();
+
+    let mut chronic_load = 0.;
+    let mut acute_load = 0.;
+    let mut result = vec![];
+
+    for day in first_day..=last_day {
+        let training_stress = stress.get(&day).copied().unwrap_or(0.);
+        let balance = chronic_load - acute_load;
+
+        chronic_load += (training_stress - chronic_load) * chronic_decay;
+        acute_load += (training_stress - acute_load) * acute_decay;
+
+        if day >= first_reported {
+            result.push(TrainingLoadDay {
+                date: day * SECONDS_PER_DAY,
+                training_stress,
+                chronic_load,
+                acute_load,
+                balance,
+                projected: day > today,
+            });
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use crate::training_load::{self, PlannedWorkout, SECONDS_PER_DAY};
+
+    #[test]
+    fn it_builds_fitness_and_fatigue_from_daily_stress() {
+        let today = 1000 * SECONDS_PER_DAY;
+        let rides = (0..60)
+            .map(|d| (today - d * SECONDS_PER_DAY + 3600, 100.))
+            .collect::<Vec<_>>();
+
+        let load = training_load::training_load(&rides, today, 7, &[]);
+
+        assert_eq!(load.len(), 7);
+        let last = load.last().unwrap();
+        assert!(!last.projected);
+        assert!(last.acute_load > 99. && last.acute_load <= 100.);
+        assert!(last.chronic_load > 70. && last.chronic_load < last.acute_load);
+        assert!(last.balance < 0.);
+    }
+
+    #[test]
+    fn it_projects_planned_workouts() {
+        let today = 1000 * SECONDS_PER_DAY;
+        let planned = vec![PlannedWorkout {
+            days_from_today: 3,
+            training_stress: 150.,
+        }];
+
+        let load = training_load::training_load(&[], today, 1, &planned);
+
+        assert_eq!(load.len(), 4);
+        assert!(load[1..].iter().all(|day| day.projected));
+        assert_eq!(load[3].training_stress, 150.);
+        assert!(load[3].acute_load > load[3].chronic_load);
+        assert_eq!(load[2].chronic_load, 0.);
+    }
+}
@@ -0,0 +1,75 @@
+// Tracks W' balance using the differential model: W' is depleted at the rate
+// power exceeds CP and reconstituted below CP in proportion to how much of it
+// has been used.
+#[derive(Clone, Copy, Debug)]
+pub struct WPrimeBalance {
+    critical_power: f64,
+    w_prime: f64,
+    balance: f64,
+}
+
+impl WPrimeBalance {
+    pub fn new(critical_power: u16, w_prime: u32) -> WPrimeBalance {
+        WPrimeBalance {
+            critical_power: critical_power as f64,
+            w_prime: w_prime as f64,
+            balance: w_prime as f64,
+        }
+    }
+
+    pub fn update(&mut self, power: u16, duration: f64) {
+        let power = power as f64;
+
+        if power > self.critical_power {
+            self.balance -= (power - self.critical_power) * duration;
+        } else {
+            let recovery = (self.critical_power - power) * (self.w_prime - self.balance)
+                / self.w_prime
+                * duration;
+            self.balance = (self.balance + recovery).min(self.w_prime);
+        }
+    }
+
+    pub fn balance(&self) -> f64 {
+        self.balance
+    }
+
+    // Highest power that can be held for the next `duration` seconds without
+    // the balance dropping below `floor`.
+    pub fn max_power(&self, floor: u32, duration: f64) -> u16 {
+        let available = (self.balance - floor as f64).max(0.);
+
+        (self.critical_power + available / duration).round() as u16
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::w_prime::WPrimeBalance;
+
+    #[test]
+    fn it_depletes_above_and_recovers_below_critical_power() {
+        let mut balance = WPrimeBalance::new(250, 20000);
+
+        for _ in 0..60 {
+            balance.update(350, 1.);
+        }
+        assert!((balance.balance() - 14000.).abs() < 1e-9);
+
+        for _ in 0..60 {
+            balance.update(150, 1.);
+        }
+        assert!(balance.balance() > 14000. && balance.balance() < 20000.);
+    }
+
+    #[test]
+    fn it_limits_power_to_protect_the_floor() {
+        let mut balance = WPrimeBalance::new(250, 20000);
+        assert_eq!(balance.max_power(5000, 1.), 15250);
+
+        for _ in 0..150 {
+            balance.update(350, 1.);
+        }
+        assert_eq!(balance.max_power(5000, 1.), 250);
+    }
+}
@@ -6,6 +6,21 @@ use fit_file::fit_file;
 use ts_rs::TS;
 use url::Url;
 
+use crate::metrics;
+
+// FIT workout step target types not exported by fit_file
+const WORKOUT_STEP_TARGET_GRADE: u8 = 5;
+const WORKOUT_STEP_TARGET_RESISTANCE: u8 = 6;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize, TS)]
+#[serde(rename_all = "snake_case")]
+#[ts(export, export_to = "../src/types/StepMode.ts")]
+pub enum StepMode {
+    Erg,
+    Simulation,
+    Resistance,
+}
+
 #[derive(Clone, Debug, PartialEq, serde::Serialize, TS)]
 #[ts(export, export_to = "../src/types/Workout.ts")]
 pub struct Workout {
@@ -16,12 +31,39 @@ pub struct Workout {
 #[derive(Clone, Debug, PartialEq, serde::Serialize, TS)]
 #[ts(export, export_to = "../src/types/WorkoutStep.ts")]
 pub struct WorkoutStep {
+    pub mode: StepMode,
     pub set_point: u32,
     pub target_power: (u32, u32),
     pub target_cadence: Option<(u32, u32)>,
+    // percent
+    pub target_grade: Option<f64>,
+    // percent of the trainer's maximum resistance
+    pub target_resistance: Option<f64>,
     pub duration: u32,
 }
 
+impl Workout {
+    // Simulation and resistance steps have no power target to estimate from,
+    // so workouts with them have no estimate.
+    pub fn estimated_training_stress(&self, ftp: u16) -> Option<f64> {
+        if self.steps.iter().any(|step| step.mode != StepMode::Erg) {
+            return None;
+        }
+
+        let power = self
+            .steps
+            .iter()
+            .flat_map(|step| std::iter::repeat_n(step.set_point as u16, step.duration as usize))
+            .collect::<Vec<_>>();
+
+        Some(metrics::training_stress(
+            power.len() as u32,
+            metrics::normalized_power(&power),
+            ftp,
+        ))
+    }
+}
+
 struct WorkoutConstructor {
     set_point_offset: f64,
     workout: Option<Workout>,
@@ -101,11 +143,47 @@ fn fit_message_callback(
                     None
                 };
 
-                let target_power = if let Some(target_power) = target_power {
-                    target_power
+                let target_grade = if step.target_type == Some(WORKOUT_STEP_TARGET_GRADE) {
+                    match target_from_fields(
+                        step.target_value,
+                        step.custom_target_low,
+                        step.custom_target_high,
+                    ) {
+                        Ok(v) => Some(grade_from_target(v)),
+                        Err(e) => {
+                            data.error = Some(e);
+                            return;
+                        }
+                    }
                 } else {
-                    data.error = Some("no power target".into());
-                    return;
+                    None
+                };
+
+                let target_resistance = if step.target_type == Some(WORKOUT_STEP_TARGET_RESISTANCE)
+                {
+                    match target_from_fields(
+                        step.target_value,
+                        step.custom_target_low,
+                        step.custom_target_high,
+                    ) {
+                        Ok(v) => Some(resistance_from_target(v)),
+                        Err(e) => {
+                            data.error = Some(e);
+                            return;
+                        }
+                    }
+                } else {
+                    None
+                };
+
+                let (mode, target_power) = match (target_power, target_grade, target_resistance) {
+                    (Some(target_power), _, _) => (StepMode::Erg, target_power),
+                    (None, Some(_), _) => (StepMode::Simulation, (0, 0)),
+                    (None, None, Some(_)) => (StepMode::Resistance, (0, 0)),
+                    (None, None, None) => {
+                        data.error = Some("no power target".into());
+                        return;
+                    }
                 };
 
                 let target_cadence = if step.target_type
@@ -138,9 +216,12 @@ fn fit_message_callback(
                     let set_point = mid_point + (data.set_point_offset * half_range);
 
                     let workout_step = WorkoutStep {
+                        mode,
                         set_point: set_point.round() as u32,
                         target_cadence,
                         target_power,
+                        target_grade,
+                        target_resistance,
                         duration,
                     };
 
@@ -227,8 +308,19 @@ fn target_from_fields(
     }
 }
 
-pub fn from_data_url(url: String, set_point_offset: f64) -> Result<Workout, String> {
-    let url = Url::parse(&url).map_err(|e| format!("parse URL: {}", e))?;
+// Grade targets are signed values in hundredths of a percent.
+fn grade_from_target(target: (u32, u32)) -> f64 {
+    (target.0 as i32 as f64 + target.1 as i32 as f64) / 2. / 100.
+}
+
+// Resistance targets are percentages of the trainer's maximum resistance.
+fn resistance_from_target(target: (u32, u32)) -> f64 {
+    ((target.0 + target.1) as f64 / 2.).min(100.)
+}
+
+// Decodes a base64 data URL, of the given media type if there is one.
+pub fn decode_data_url(url: &str, media_type: Option<&str>) -> Result<Vec<u8>, String> {
+    let url = Url::parse(url).map_err(|e| format!("parse URL: {}", e))?;
 
     if url.scheme() != "data"
         || url.query().is_some()
@@ -240,13 +332,26 @@ pub fn from_data_url(url: String, set_point_offset: f64) -> Result<Workout, Stri
 
     let parts = url.path().split(',').collect::<Vec<_>>();
 
-    if parts.len() != 2 || parts[0] != "application/octet-stream;base64" {
-        return Err("invalid data URL".into());
+    let data_type = match parts.as_slice() {
+        [header, _] => header.strip_suffix(";base64"),
+        _ => None,
+    };
+
+    match (data_type, media_type) {
+        (Some(data_type), Some(media_type)) if data_type != media_type => {
+            return Err("invalid data URL".into())
+        }
+        (None, _) => return Err("invalid data URL".into()),
+        _ => {}
     }
 
-    let data = general_purpose::STANDARD
+    general_purpose::STANDARD
         .decode(parts[1])
-        .map_err(|e| format!("decode base64: {}", e))?;
+        .map_err(|e| format!("decode base64: {}", e))
+}
+
+pub fn from_data_url(url: String, set_point_offset: f64) -> Result<Workout, String> {
+    let data = decode_data_url(&url, Some("application/octet-stream"))?;
 
     load_workout(&*data, set_point_offset)
 }
@@ -279,7 +384,7 @@ pub fn load_workout(data: impl std::io::Read, set_point_offset: f64) -> Result<W
 
 #[cfg(test)]
 mod test {
-    use crate::workout::{self, WorkoutStep};
+    use crate::workout::{self, StepMode, Workout, WorkoutStep};
     use std::fs::File;
     use std::io::BufReader;
 
@@ -295,110 +400,164 @@ mod test {
                 title: "Threshold 4x 8\"".into(),
                 steps: vec![
                     WorkoutStep {
+                        mode: StepMode::Erg,
                         duration: 480,
                         set_point: 113,
                         target_cadence: None,
                         target_power: (100, 125),
+                        target_grade: None,
+                        target_resistance: None,
                     },
                     WorkoutStep {
+                        mode: StepMode::Erg,
                         duration: 60,
                         set_point: 138,
                         target_cadence: Some((85, 95)),
                         target_power: (125, 150),
+                        target_grade: None,
+                        target_resistance: None,
                     },
                     WorkoutStep {
+                        mode: StepMode::Erg,
                         duration: 60,
                         set_point: 138,
                         target_cadence: Some((95, 105)),
                         target_power: (125, 150),
+                        target_grade: None,
+                        target_resistance: None,
                     },
                     WorkoutStep {
+                        mode: StepMode::Erg,
                         duration: 60,
                         set_point: 138,
                         target_cadence: Some((105, 115)),
                         target_power: (125, 150),
+                        target_grade: None,
+                        target_resistance: None,
                     },
                     WorkoutStep {
+                        mode: StepMode::Erg,
                         duration: 60,
                         set_point: 138,
                         target_cadence: Some((115, 125)),
                         target_power: (125, 150),
+                        target_grade: None,
+                        target_resistance: None,
                     },
                     WorkoutStep {
+                        mode: StepMode::Erg,
                         duration: 60,
                         set_point: 138,
                         target_cadence: Some((105, 115)),
                         target_power: (125, 150),
+                        target_grade: None,
+                        target_resistance: None,
                     },
                     WorkoutStep {
+                        mode: StepMode::Erg,
                         duration: 60,
                         set_point: 138,
                         target_cadence: Some((95, 105)),
                         target_power: (125, 150),
+                        target_grade: None,
+                        target_resistance: None,
                     },
                     WorkoutStep {
+                        mode: StepMode::Erg,
                         duration: 60,
                         set_point: 138,
                         target_cadence: Some((85, 95)),
                         target_power: (125, 150),
+                        target_grade: None,
+                        target_resistance: None,
                     },
                     WorkoutStep {
+                        mode: StepMode::Erg,
                         duration: 300,
                         set_point: 113,
                         target_cadence: None,
                         target_power: (100, 125),
+                        target_grade: None,
+                        target_resistance: None,
                     },
                     WorkoutStep {
+                        mode: StepMode::Erg,
                         duration: 480,
                         set_point: 244,
                         target_cadence: None,
                         target_power: (238, 250),
+                        target_grade: None,
+                        target_resistance: None,
                     },
                     WorkoutStep {
+                        mode: StepMode::Erg,
                         set_point: 125,
                         target_power: (112, 138),
+                        target_grade: None,
+                        target_resistance: None,
                         target_cadence: None,
                         duration: 120
                     },
                     WorkoutStep {
+                        mode: StepMode::Erg,
                         duration: 480,
                         set_point: 244,
                         target_cadence: None,
                         target_power: (238, 250),
+                        target_grade: None,
+                        target_resistance: None,
                     },
                     WorkoutStep {
+                        mode: StepMode::Erg,
                         set_point: 125,
                         target_power: (112, 138),
+                        target_grade: None,
+                        target_resistance: None,
                         target_cadence: None,
                         duration: 120
                     },
                     WorkoutStep {
+                        mode: StepMode::Erg,
                         duration: 480,
                         set_point: 244,
                         target_cadence: None,
                         target_power: (238, 250),
+                        target_grade: None,
+                        target_resistance: None,
                     },
                     WorkoutStep {
+                        mode: StepMode::Erg,
                         set_point: 125,
                         target_power: (112, 138),
+                        target_grade: None,
+                        target_resistance: None,
                         target_cadence: None,
                         duration: 120
                     },
                     WorkoutStep {
+                        mode: StepMode::Erg,
                         duration: 480,
                         set_point: 244,
                         target_cadence: None,
                         target_power: (238, 250),
+                        target_grade: None,
+                        target_resistance: None,
                     },
                     WorkoutStep {
+                        mode: StepMode::Erg,
                         set_point: 125,
                         target_power: (112, 138),
+                        target_grade: None,
+                        target_resistance: None,
                         target_cadence: None,
                         duration: 120
                     },
                     WorkoutStep {
+                        mode: StepMode::Erg,
                         set_point: 113,
                         target_power: (100, 125),
+                        target_grade: None,
+                        target_resistance: None,
                         target_cadence: None,
                         duration: 600
                     },
@@ -406,4 +565,55 @@ mod test {
             }
         );
     }
+
+    #[test]
+    fn it_parses_resistance_targets() {
+        let target =
+            workout::target_from_fields(Some(0), Some(40), Some(60)).expect("custom target");
+        assert_eq!(workout::resistance_from_target(target), 50.);
+
+        // above the trainer's maximum is limited to it
+        assert_eq!(workout::resistance_from_target((110, 130)), 100.);
+
+        assert!(workout::target_from_fields(Some(3), None, None).is_err());
+    }
+
+    #[test]
+    fn it_parses_grade_targets() {
+        let target =
+            workout::target_from_fields(Some(0), Some(500), Some(700)).expect("custom target");
+        assert_eq!(workout::grade_from_target(target), 6.);
+
+        // negative grades are signed values in the unsigned fields
+        assert_eq!(
+            workout::grade_from_target((-300i32 as u32, -100i32 as u32)),
+            -2.
+        );
+    }
+
+    #[test]
+    fn it_only_estimates_training_stress_for_power_targets() {
+        let step = |mode, set_point| WorkoutStep {
+            mode,
+            set_point,
+            target_power: (set_point, set_point),
+            target_cadence: None,
+            target_grade: None,
+            target_resistance: None,
+            duration: 3600,
+        };
+
+        let workout = Workout {
+            title: "test".into(),
+            steps: vec![step(StepMode::Erg, 250)],
+        };
+        let stress = workout.estimated_training_stress(250).expect("estimate");
+        assert!((stress - 100.).abs() < 0.1, "stress: {}", stress);
+
+        let workout = Workout {
+            title: "test".into(),
+            steps: vec![step(StepMode::Erg, 250), step(StepMode::Simulation, 0)],
+        };
+        assert_eq!(workout.estimated_training_stress(250), None);
+    }
 }
@@ -14,6 +14,7 @@ import { Workout } from "./types/Workout";
 import { FitnessEquipmentUpdate } from "./types/FitnessEquipmentUpdate";
 import { HeartRateUpdate } from "./types/HeartRateUpdate";
 import { WorkoutStatus } from "./types/WorkoutStatus";
+import { WorkoutWarning } from "./types/WorkoutWarning";
 import { TauriEvent } from "./types";
 
 const useStyles = createUseStyles({
@@ -44,6 +45,7 @@ function WorkoutMain({ workout }: { workout: Workout }) {
   const [heartRate, setHeartRate] = useState<null | number>(null);
   const [cadence, setCadence] = useState<null | number>(null);
   const [power, setPower] = useState<null | number>(null);
+  const [warnings, setWarnings] = useState<WorkoutWarning[]>([]);
 
   useEffect(() => {
     const cleanup = listen(
@@ -86,6 +88,19 @@ function WorkoutMain({ workout }: { workout: Workout }) {
     };
   }, []);
 
+  useEffect(() => {
+    const cleanup = listen(
+      "workout_warnings",
+      (event: TauriEvent<WorkoutWarning[]>) => {
+        setWarnings(event.payload);
+      },
+    );
+
+    return () => {
+      cleanup.then((f) => f());
+    };
+  }, []);
+
   const startWorkout = async () => {
     try {
       await invoke("start_workout");
@@ -146,6 +161,12 @@ function WorkoutMain({ workout }: { workout: Workout }) {
         stepIndex={stepIndex}
         stepElapsed={stepElapsed}
       />
+      {warnings.map((warning, i) => (
+        <div key={i}>
+          {warning.step_index !== null && `Step ${warning.step_index + 1}: `}
+          {warning.message}
+        </div>
+      ))}
       {error !== null && <div>{error}</div>}
     </div>
   );
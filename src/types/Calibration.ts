@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { CalibrationKind } from "./CalibrationKind";
+
+export interface Calibration { kind: CalibrationKind, date: number, zero_offset: number | null, spin_down_time: number | null, temperature: number | null, }
\ No newline at end of file
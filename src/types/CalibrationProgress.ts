@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { CalibrationCondition } from "./CalibrationCondition";
+
+export interface CalibrationProgress { spin_down_pending: boolean, zero_offset_pending: boolean, temperature_condition: CalibrationCondition, speed_condition: CalibrationCondition, temperature: number | null, target_speed: number | null, target_spin_down_time: number | null, }
\ No newline at end of file
@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export interface CalibrationResult { spin_down_success: boolean, zero_offset_success: boolean, temperature: number | null, zero_offset: number | null, spin_down_time: number | null, }
\ No newline at end of file
@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export interface Capabilities { maximum_resistance: number | null, basic_resistance: boolean, target_power: boolean, simulation: boolean, }
\ No newline at end of file
@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { CriticalPowerModel } from "./CriticalPowerModel";
+
+export interface CriticalPowerEstimate { two_parameter: CriticalPowerModel | null, three_parameter: CriticalPowerModel | null, ftp_estimate: number | null, configured_ftp: number | null, ftp_update_suggested: boolean, }
\ No newline at end of file
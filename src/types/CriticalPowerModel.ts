@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export interface CriticalPowerModel { critical_power: number, w_prime: number, time_offset: number, }
\ No newline at end of file
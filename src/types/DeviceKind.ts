@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export type DeviceKind = "heart_rate_monitor" | "fitness_equipment" | "bicycle_power" | "bike_speed_cadence" | "bike_speed" | "bike_cadence";
\ No newline at end of file
@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { DeviceKind } from "./DeviceKind";
+
+export interface FoundDevice { kind: DeviceKind, device_id: number, transmission_type: number, rssi: number | null, manufacturer: number | null, }
\ No newline at end of file
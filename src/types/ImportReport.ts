@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { ImportFailure } from "./ImportFailure";
+
+export interface ImportReport { imported: number, duplicates: number, failures: Array<ImportFailure>, }
\ No newline at end of file
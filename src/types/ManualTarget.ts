@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { StepMode } from "./StepMode";
+
+export interface ManualTarget { mode: StepMode, value: number, }
\ No newline at end of file
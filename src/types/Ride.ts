@@ -0,0 +1,6 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { Lap } from "./Lap";
+import type { RideSummary } from "./RideSummary";
+import type { Sample } from "./Sample";
+
+export interface Ride { summary: RideSummary, samples: Array<Sample>, laps: Array<Lap>, }
\ No newline at end of file
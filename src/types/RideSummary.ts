@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export interface RideSummary { id: number, title: string, start_time: number, duration: number, average_power: number, maximum_power: number, normalized_power: number, average_cadence: number | null, average_heart_rate: number | null, maximum_heart_rate: number | null, work: number, intensity_factor: number | null, training_stress: number | null, distance: number | null, calibration_required: boolean, }
\ No newline at end of file
@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export interface RiderProfile { rider_weight: number, bike_weight: number, gear_ratio: number | null, drag_area: number | null, rolling_resistance: number | null, }
\ No newline at end of file
@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { RoutePoint } from "./RoutePoint";
+
+export interface Route { name: string | null, points: Array<RoutePoint>, distance: number, ascent: number, }
\ No newline at end of file
@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export interface RoutePosition { distance: number, remaining_distance: number, grade: number, elevation: number, remaining_ascent: number, latitude: number | null, longitude: number | null, }
\ No newline at end of file
@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export interface Sample { elapsed: number, power: number | null, cadence: number | null, heart_rate: number | null, speed: number | null, distance: number | null, }
\ No newline at end of file
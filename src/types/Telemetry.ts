@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export interface Telemetry { elapsed: number, power_3s: number, power_10s: number, power_30s: number, average_power: number, normalized_power: number, intensity_factor: number | null, training_stress: number | null, work: number, average_cadence: number | null, average_heart_rate: number | null, w_prime_balance: number | null, cadence: number | null, speed: number | null, distance: number | null, gear: number, }
\ No newline at end of file
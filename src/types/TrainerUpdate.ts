@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { FeState } from "./FeState";
+
+export interface TrainerUpdate { elapsed: number, distance: number, speed: number | null, state: FeState, power_calibration_required: boolean, resistance_calibration_required: boolean, user_configuration_required: boolean, }
\ No newline at end of file
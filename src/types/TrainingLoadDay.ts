@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export interface TrainingLoadDay { date: number, training_stress: number, chronic_load: number, acute_load: number, balance: number, projected: boolean, }
\ No newline at end of file
@@ -1,3 +1,5 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { HeartRateTarget } from "./HeartRateTarget";
+import type { StepMode } from "./StepMode";
 
-export interface WorkoutStatus { step_index: number, step_elapsed: number, }
\ No newline at end of file
+export interface WorkoutStatus { step_index: number, step_elapsed: number, mode: StepMode, target_power: number, target_grade: number | null, target_limited: boolean, power_match_offset: number | null, heart_rate_target: HeartRateTarget | null, cadence_limited: boolean, paused: boolean, }
\ No newline at end of file
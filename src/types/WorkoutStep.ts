@@ -1,3 +1,4 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { StepMode } from "./StepMode";
 
-export interface WorkoutStep { set_point: number, target_power: [number, number], target_cadence: [number, number] | null, duration: number, }
\ No newline at end of file
+export interface WorkoutStep { mode: StepMode, set_point: number, target_power: [number, number], target_cadence: [number, number] | null, target_grade: number | null, target_resistance: number | null, duration: number, }
\ No newline at end of file